@@ -0,0 +1,157 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use anyhow::{anyhow, Context, Result};
+use bytes::Bytes;
+use rand::Rng;
+
+use crate::work::pick_weighted;
+
+/// Name of the optional manifest of the directory, `NAME WEIGHT` per line.
+/// Files it doesn't list weigh 1
+pub const MANIFEST: &str = "weights.txt";
+
+/// The files of a directory, one picked at random as the body of every
+/// request. Files are read once upfront.
+#[derive(Debug)]
+pub struct BodyDir {
+	names: Vec<String>,
+	bodies: Vec<Bytes>,
+	/// Cumulative weights of `bodies`
+	cumulative: Vec<u64>,
+	/// Times every body was sent
+	sent: Vec<AtomicU64>,
+}
+
+impl BodyDir {
+	pub fn load(dir: &Path) -> Result<Self> {
+		let entries = std::fs::read_dir(dir)
+			.with_context(|| format!("fail to read directory {}", dir.display()))?;
+		let mut files = BTreeMap::new();
+		for entry in entries {
+			let path = entry?.path();
+			let name = path
+				.file_name()
+				.map(|name| name.to_string_lossy().into_owned())
+				.unwrap_or_default();
+			if path.is_file() && name != MANIFEST {
+				files.insert(name, path);
+			}
+		}
+		let mut weights: BTreeMap<String, u64> = BTreeMap::new();
+		let manifest = dir.join(MANIFEST);
+		if manifest.is_file() {
+			let content = std::fs::read_to_string(&manifest)
+				.with_context(|| format!("fail to read {}", manifest.display()))?;
+			for (no, line) in content
+				.lines()
+				.enumerate()
+				.map(|(i, line)| (i + 1, line.trim()))
+			{
+				if line.is_empty() {
+					continue;
+				}
+				let invalid = || anyhow!("invalid weight at line {} of {}: {}", no, MANIFEST, line);
+				let (name, weight) = line.rsplit_once(char::is_whitespace).ok_or_else(invalid)?;
+				let name = name.trim();
+				if !files.contains_key(name) {
+					return Err(anyhow!("{} has no file {}", dir.display(), name));
+				}
+				weights.insert(name.to_string(), weight.parse().map_err(|_| invalid())?);
+			}
+		}
+		let mut body_dir = BodyDir {
+			names: vec![],
+			bodies: vec![],
+			cumulative: vec![],
+			sent: vec![],
+		};
+		let mut sum = 0;
+		for (name, path) in files {
+			let weight = weights.get(&name).copied().unwrap_or(1);
+			if weight == 0 {
+				continue;
+			}
+			let body =
+				std::fs::read(&path).with_context(|| format!("fail to read {}", path.display()))?;
+			sum += weight;
+			body_dir.names.push(name);
+			body_dir.bodies.push(Bytes::from(body));
+			body_dir.cumulative.push(sum);
+			body_dir.sent.push(AtomicU64::new(0));
+		}
+		if body_dir.bodies.is_empty() {
+			return Err(anyhow!("{} has no file to send", dir.display()));
+		}
+		Ok(body_dir)
+	}
+
+	/// Pick a body according to the weights and count it as sent
+	pub fn pick<R: Rng>(&self, rng: &mut R) -> Bytes {
+		let index = pick_weighted(&self.cumulative, rng);
+		self.sent[index].fetch_add(1, Ordering::Relaxed);
+		self.bodies[index].clone()
+	}
+
+	/// Times every file was sent, by name
+	pub fn sent(&self) -> BTreeMap<String, u64> {
+		self.names
+			.iter()
+			.zip(&self.sent)
+			.map(|(name, sent)| (name.clone(), sent.load(Ordering::Relaxed)))
+			.collect()
+	}
+}
+
+/// A copy that counts its own sends, for another run
+impl Clone for BodyDir {
+	fn clone(&self) -> Self {
+		BodyDir {
+			names: self.names.clone(),
+			bodies: self.bodies.clone(),
+			cumulative: self.cumulative.clone(),
+			sent: self.sent.iter().map(|_| AtomicU64::new(0)).collect(),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use rand::rngs::StdRng;
+	use rand::SeedableRng;
+
+	use crate::body_dir::{BodyDir, MANIFEST};
+
+	#[test]
+	fn load_should_follow_manifest_weights() {
+		let dir = std::env::temp_dir().join("rey-body-dir-test");
+		std::fs::create_dir_all(&dir).unwrap();
+		std::fs::write(dir.join("a.json"), "a").unwrap();
+		std::fs::write(dir.join("b.json"), "b").unwrap();
+		std::fs::write(dir.join("c.json"), "c").unwrap();
+		std::fs::write(dir.join(MANIFEST), "a.json 3\n\nc.json 0\n").unwrap();
+		let body_dir = BodyDir::load(&dir).unwrap();
+		let mut rng = StdRng::seed_from_u64(0);
+		for _ in 0..1000 {
+			assert_ne!(&body_dir.pick(&mut rng)[..], b"c");
+		}
+		let sent = body_dir.sent();
+		assert_eq!(sent.keys().collect::<Vec<_>>(), vec!["a.json", "b.json"]);
+		assert!((700..800).contains(&sent["a.json"]), "{:?}", sent);
+
+		std::fs::write(dir.join(MANIFEST), "d.json 1\n").unwrap();
+		let err = BodyDir::load(&dir).unwrap_err();
+		assert_eq!(
+			err.to_string(),
+			format!("{} has no file d.json", dir.display())
+		);
+		std::fs::write(dir.join(MANIFEST), "a.json x\n").unwrap();
+		let err = BodyDir::load(&dir).unwrap_err();
+		assert_eq!(
+			err.to_string(),
+			"invalid weight at line 1 of weights.txt: a.json x"
+		);
+		std::fs::remove_dir_all(&dir).unwrap();
+	}
+}
@@ -1,52 +1,396 @@
-use std::time::Duration;
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::future::Future;
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 use anyhow::{anyhow, Context, Error, Result};
-use http::header::{ACCEPT, CONTENT_TYPE, HOST, USER_AGENT};
-use http::{HeaderMap, HeaderName, HeaderValue};
+use http::header::{ACCEPT, ACCEPT_ENCODING, CONTENT_TYPE, HOST, USER_AGENT};
+use http::{HeaderMap, HeaderName, HeaderValue, Version};
+use hyper::client::connect::dns::Name;
+use log::warn;
+use percent_encoding::percent_decode_str;
+use reqwest::cookie::{CookieStore, Jar};
+use reqwest::dns::{Addrs, Resolve, Resolving};
 use reqwest::redirect::Policy;
-use reqwest::{Client, Proxy};
+use reqwest::{Client, Identity, Proxy, Url};
 
+tokio::task_local! {
+	static DNS_DURATION: Cell<Duration>;
+}
+
+/// Address family of the connections
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum IpFamily {
+	V4,
+	V6,
+}
+
+impl IpFamily {
+	fn contains(&self, addr: &SocketAddr) -> bool {
+		match self {
+			IpFamily::V4 => addr.is_ipv4(),
+			IpFamily::V6 => addr.is_ipv6(),
+		}
+	}
+}
+
+impl std::fmt::Display for IpFamily {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			IpFamily::V4 => write!(f, "IPv4"),
+			IpFamily::V6 => write!(f, "IPv6"),
+		}
+	}
+}
+
+/// Resolves names with the system resolver like reqwest does, and adds the
+/// time spent to the request currently running inside [`timed_dns`].
+struct TimingResolver {
+	/// Only keep the addresses of this family
+	family: Option<IpFamily>,
+}
+
+impl Resolve for TimingResolver {
+	fn resolve(&self, name: Name) -> Resolving {
+		let family = self.family;
+		Box::pin(async move {
+			let start = Instant::now();
+			let addrs: Vec<SocketAddr> = tokio::net::lookup_host((name.as_str(), 0))
+				.await?
+				.filter(|addr| family.is_none_or(|family| family.contains(addr)))
+				.collect();
+			let elapsed = start.elapsed();
+			let _ = DNS_DURATION.try_with(|cell| cell.set(cell.get() + elapsed));
+			if let (Some(family), true) = (family, addrs.is_empty()) {
+				return Err(format!("no {} address for {}", family, name.as_str()).into());
+			}
+			Ok(Box::new(addrs.into_iter()) as Addrs)
+		})
+	}
+}
+
+/// Run `fut` and return the time it spent on DNS lookups. reqwest only exposes
+/// name resolution, so it is zero when a pooled connection was reused, and
+/// connect and TLS handshake time can't be told apart from waiting for the
+/// response.
+pub async fn timed_dns<F: Future>(fut: F) -> (F::Output, Duration) {
+	DNS_DURATION
+		.scope(Cell::new(Duration::ZERO), async move {
+			let output = fut.await;
+			(output, DNS_DURATION.with(|cell| cell.get()))
+		})
+		.await
+}
+
+/// Relay loopback TCP connections to the Unix socket at `path`, since reqwest
+/// can't dial Unix sockets itself. Returns the address to connect to instead.
+#[cfg(unix)]
+pub async fn relay_unix_socket(path: PathBuf) -> Result<SocketAddr> {
+	use tokio::net::{TcpListener, UnixStream};
+
+	UnixStream::connect(&path)
+		.await
+		.with_context(|| format!("fail to connect to {}", path.display()))?;
+	let listener = TcpListener::bind("127.0.0.1:0").await?;
+	let addr = listener.local_addr()?;
+	tokio::spawn(async move {
+		while let Ok((mut tcp, _)) = listener.accept().await {
+			let path = path.clone();
+			tokio::spawn(async move {
+				match UnixStream::connect(&path).await {
+					Ok(mut unix) => {
+						let _ = tokio::io::copy_bidirectional(&mut tcp, &mut unix).await;
+					}
+					Err(err) => warn!("fail to connect to {}: {}", path.display(), err),
+				}
+			});
+		}
+	});
+	Ok(addr)
+}
+
+#[cfg(not(unix))]
+pub async fn relay_unix_socket(_path: PathBuf) -> Result<SocketAddr> {
+	Err(anyhow!("unix sockets aren't supported on this platform"))
+}
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq)]
+pub enum HttpVersion {
+	#[value(name = "1.0")]
+	Http1_0,
+	#[value(name = "1.1")]
+	Http1_1,
+	#[value(name = "2")]
+	Http2,
+}
+
+impl HttpVersion {
+	pub fn version(&self) -> Version {
+		match self {
+			HttpVersion::Http1_0 => Version::HTTP_10,
+			HttpVersion::Http1_1 => Version::HTTP_11,
+			HttpVersion::Http2 => Version::HTTP_2,
+		}
+	}
+}
+
+impl std::fmt::Display for HttpVersion {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			HttpVersion::Http1_0 => write!(f, "HTTP/1.0"),
+			HttpVersion::Http1_1 => write!(f, "HTTP/1.1"),
+			HttpVersion::Http2 => write!(f, "HTTP/2"),
+		}
+	}
+}
+
+/// Client certificate presented for mutual TLS
+#[derive(Debug, Clone)]
+pub enum ClientIdentity {
+	/// PEM encoded certificate chain and PKCS#8 private key
+	Pem { cert: PathBuf, key: PathBuf },
+	/// DER encoded PKCS#12 archive
+	Pkcs12 { file: PathBuf, password: String },
+}
+
+impl TryFrom<ClientIdentity> for Identity {
+	type Error = anyhow::Error;
+
+	fn try_from(identity: ClientIdentity) -> Result<Identity> {
+		let read = |path: &PathBuf| {
+			std::fs::read(path).with_context(|| format!("fail to read {}", path.display()))
+		};
+		match identity {
+			ClientIdentity::Pem { cert, key } => {
+				Identity::from_pkcs8_pem(&read(&cert)?, &read(&key)?)
+					.context("invalid client certificate or key")
+			}
+			ClientIdentity::Pkcs12 { file, password } => {
+				Identity::from_pkcs12_der(&read(&file)?, &password)
+					.context("invalid PKCS#12 file or password")
+			}
+		}
+	}
+}
+
+/// Preset cookies sent with every request, followed by the cookies responses
+/// set when a jar is kept.
+struct Cookies {
+	preset: Vec<String>,
+	jar: Option<Jar>,
+}
+
+impl CookieStore for Cookies {
+	fn set_cookies(&self, headers: &mut dyn Iterator<Item = &HeaderValue>, url: &Url) {
+		if let Some(jar) = &self.jar {
+			jar.set_cookies(headers, url);
+		}
+	}
+
+	fn cookies(&self, url: &Url) -> Option<HeaderValue> {
+		let stored = self.jar.as_ref().and_then(|jar| jar.cookies(url));
+		let mut cookies = self.preset.join("; ");
+		if let Some(stored) = stored.as_ref().and_then(|stored| stored.to_str().ok()) {
+			if !cookies.is_empty() {
+				cookies.push_str("; ");
+			}
+			cookies.push_str(stored);
+		}
+		if cookies.is_empty() {
+			return None;
+		}
+		HeaderValue::try_from(cookies).ok()
+	}
+}
+
+#[derive(Debug, Clone)]
 pub struct ClientBuilder {
 	pub headers: Vec<String>,
+	/// Total time of a request
 	pub timeout: Option<Duration>,
+	pub connect_timeout: Option<Duration>,
 	pub content_type: HeaderValue,
+	/// Accept compressed responses without decompressing them, so their size is
+	/// the number of bytes on the wire
+	pub wire_size: bool,
 	pub accept: Option<HeaderValue>,
 	pub user_agent: HeaderValue,
-	pub proxy: Option<String>,
+	/// Proxy for http and https targets, https is tunneled with CONNECT over a
+	/// http(s) proxy. Credentials of a socks5 proxy are its username and password
+	pub proxy: Option<Url>,
+	/// Proxy credentials, the ones embedded in the proxy url are used without them
+	pub proxy_auth: Option<(String, String)>,
 	pub host: Option<HeaderValue>,
 	pub disable_redirect: bool,
+	/// Redirects followed before failing, the reqwest default policy without it
+	pub max_redirects: Option<usize>,
+	/// Don't keep idle connections, so every request opens a new one. This holds
+	/// for HTTP/2 too, requests aren't multiplexed over a shared connection then
+	pub disable_keepalive: bool,
+	/// Idle connections kept per host
+	pub max_connections: Option<usize>,
+	/// Force a protocol version, HTTP/2 uses prior knowledge over cleartext and ALPN over TLS
+	pub http_version: Option<HttpVersion>,
+	/// Accept invalid TLS certificates
+	pub insecure: bool,
+	pub identity: Option<ClientIdentity>,
+	/// Source address of the connections
+	pub local_address: Option<IpAddr>,
+	/// Only connect over this family, the other addresses of a host are
+	/// ignored. Urls with an address and the addresses of `resolve` are used as they are
+	pub ip_family: Option<IpFamily>,
+	/// Addresses used instead of resolving the host. reqwest always connects to
+	/// the port of the url, so the port of the address is ignored
+	pub resolve: Vec<(String, SocketAddr)>,
+	/// `name=value` cookies sent with every request
+	pub cookies: Vec<String>,
+	/// Keep the cookies set by responses and send them back, the jar belongs to
+	/// the client so it is shared by every worker using it
+	pub cookie_jar: bool,
 }
 
-impl TryFrom<ClientBuilder> for Client {
-	type Error = anyhow::Error;
+/// The defaults of the command line
+impl Default for ClientBuilder {
+	fn default() -> Self {
+		ClientBuilder {
+			headers: vec![],
+			timeout: Some(Duration::from_secs(20)),
+			connect_timeout: Some(Duration::from_secs(20)),
+			content_type: HeaderValue::from_static("text/html"),
+			wire_size: false,
+			accept: None,
+			user_agent: HeaderValue::from_static("rey/0.1.0"),
+			proxy: None,
+			proxy_auth: None,
+			host: None,
+			disable_redirect: false,
+			max_redirects: None,
+			disable_keepalive: false,
+			max_connections: None,
+			http_version: None,
+			insecure: false,
+			identity: None,
+			local_address: None,
+			ip_family: None,
+			resolve: vec![],
+			cookies: vec![],
+			cookie_jar: false,
+		}
+	}
+}
 
-	fn try_from(cb: ClientBuilder) -> anyhow::Result<Client> {
-		let mut headers = try_into_headers(&cb.headers)?;
-		headers.insert(CONTENT_TYPE, cb.content_type);
-		headers.insert(USER_AGENT, cb.user_agent);
-		if let Some(accept) = cb.accept {
-			headers.insert(ACCEPT, accept);
+impl ClientBuilder {
+	/// Headers sent with every request, cookies aside
+	pub fn default_headers(&self) -> Result<HeaderMap> {
+		let mut headers = try_into_headers(&self.headers)?;
+		headers.insert(CONTENT_TYPE, self.content_type.clone());
+		headers.insert(USER_AGENT, self.user_agent.clone());
+		if let Some(accept) = &self.accept {
+			headers.insert(ACCEPT, accept.clone());
+		}
+		if self.wire_size && !headers.contains_key(ACCEPT_ENCODING) {
+			headers.insert(
+				ACCEPT_ENCODING,
+				HeaderValue::from_static("gzip, br, deflate"),
+			);
 		}
-		if let Some(host) = cb.host {
-			headers.insert(HOST, host);
+		if let Some(host) = &self.host {
+			headers.insert(HOST, host.clone());
 		}
+		Ok(headers)
+	}
+}
+
+impl TryFrom<ClientBuilder> for Client {
+	type Error = anyhow::Error;
 
+	fn try_from(cb: ClientBuilder) -> anyhow::Result<Client> {
+		let headers = cb.default_headers()?;
 		let mut builder = Client::builder();
-		builder = builder.default_headers(headers);
+		builder = builder
+			.default_headers(headers)
+			.dns_resolver(Arc::new(TimingResolver {
+				family: cb.ip_family,
+			}));
 
+		if cb.wire_size {
+			builder = builder.no_gzip().no_brotli().no_deflate();
+		}
 		if let Some(timeout) = cb.timeout {
-			builder = builder.timeout(timeout).connect_timeout(timeout);
+			builder = builder.timeout(timeout);
+		}
+		if let Some(timeout) = cb.connect_timeout {
+			builder = builder.connect_timeout(timeout);
+		}
+		match cb.http_version {
+			Some(HttpVersion::Http1_0 | HttpVersion::Http1_1) => builder = builder.http1_only(),
+			Some(HttpVersion::Http2) => builder = builder.http2_prior_knowledge(),
+			None => {}
+		}
+		match (cb.disable_redirect, cb.max_redirects) {
+			(true, _) | (_, Some(0)) => builder = builder.redirect(Policy::none()),
+			// the limit of reqwest counts the original url along with the redirects
+			(false, Some(max)) => builder = builder.redirect(Policy::limited(max + 1)),
+			(false, None) => {}
+		}
+		if cb.disable_keepalive {
+			builder = builder.pool_max_idle_per_host(0);
+		} else if let Some(max) = cb.max_connections {
+			builder = builder.pool_max_idle_per_host(max);
+		}
+		if let Some(addr) = cb.local_address {
+			builder = builder.local_address(addr);
+		}
+		if cb.insecure {
+			warn!("TLS certificate verification is disabled");
+			builder = builder.danger_accept_invalid_certs(true);
 		}
-		if cb.disable_redirect {
-			builder = builder.redirect(Policy::none())
+		let mut overrides: HashMap<String, Vec<SocketAddr>> = HashMap::new();
+		for (host, addr) in cb.resolve {
+			overrides.entry(host).or_default().push(addr);
+		}
+		for (host, addrs) in overrides {
+			builder = builder.resolve_to_addrs(&host, &addrs);
+		}
+		if cb.cookie_jar || !cb.cookies.is_empty() {
+			builder = builder.cookie_provider(Arc::new(Cookies {
+				preset: cb.cookies,
+				jar: cb.cookie_jar.then(Jar::default),
+			}));
+		}
+		if let Some(identity) = cb.identity {
+			builder = builder.identity(identity.try_into()?);
 		}
 		if let Some(proxy) = cb.proxy {
-			builder = builder.proxy(Proxy::all(proxy).context("invalid proxy")?);
+			builder = builder.proxy(try_into_proxy(proxy, cb.proxy_auth)?);
 		}
 		builder.build().context("fail to build a http client")
 	}
 }
 
+/// Send the credentials as `Proxy-Authorization`, taking them out of the url
+fn try_into_proxy(mut url: Url, auth: Option<(String, String)>) -> Result<Proxy> {
+	let decode = |s: &str| percent_decode_str(s).decode_utf8_lossy().into_owned();
+	let auth = auth.or_else(|| {
+		(!url.username().is_empty()).then(|| {
+			(
+				decode(url.username()),
+				decode(url.password().unwrap_or_default()),
+			)
+		})
+	});
+	let _ = url.set_username("");
+	let _ = url.set_password(None);
+	let proxy = Proxy::all(url).context("invalid proxy")?;
+	Ok(match auth {
+		Some((username, password)) => proxy.basic_auth(&username, &password),
+		None => proxy,
+	})
+}
+
 type Header = (HeaderName, HeaderValue);
 
 fn try_into_headers(strs: &[String]) -> Result<HeaderMap, Error> {
@@ -76,7 +420,109 @@ fn try_into_header(s: &str) -> Result<Header> {
 
 #[cfg(test)]
 mod test {
-	use crate::client::{try_into_header, try_into_headers};
+	use std::path::PathBuf;
+
+	use http::HeaderValue;
+	use reqwest::cookie::{CookieStore, Jar};
+	use reqwest::{Identity, Url};
+
+	use std::str::FromStr;
+
+	use hyper::client::connect::dns::Name;
+	use reqwest::dns::Resolve;
+
+	use crate::client::{
+		try_into_header, try_into_headers, ClientIdentity, Cookies, IpFamily, TimingResolver,
+	};
+
+	#[tokio::test]
+	async fn resolver_should_keep_the_family() {
+		let resolver = TimingResolver {
+			family: Some(IpFamily::V4),
+		};
+		let addrs: Vec<_> = resolver
+			.resolve(Name::from_str("localhost").unwrap())
+			.await
+			.unwrap()
+			.collect();
+		assert!(!addrs.is_empty());
+		assert!(addrs.iter().all(|addr| addr.is_ipv4()));
+		let resolver = TimingResolver {
+			family: Some(IpFamily::V6),
+		};
+		let err = match resolver.resolve(Name::from_str("127.0.0.1").unwrap()).await {
+			Ok(_) => panic!("127.0.0.1 has no IPv6 address"),
+			Err(err) => err,
+		};
+		assert_eq!(err.to_string(), "no IPv6 address for 127.0.0.1");
+	}
+
+	#[test]
+	fn cookies_should_send_preset_before_stored() {
+		let url: Url = "http://localhost/".parse().unwrap();
+		let preset = vec!["a=1".to_string(), "b=2".to_string()];
+		let cookies = Cookies {
+			preset: preset.clone(),
+			jar: None,
+		};
+		let set = HeaderValue::from_static("session=abc");
+		cookies.set_cookies(&mut std::iter::once(&set), &url);
+		assert_eq!(cookies.cookies(&url).unwrap(), "a=1; b=2");
+
+		let cookies = Cookies {
+			preset,
+			jar: Some(Jar::default()),
+		};
+		cookies.set_cookies(&mut std::iter::once(&set), &url);
+		assert_eq!(cookies.cookies(&url).unwrap(), "a=1; b=2; session=abc");
+
+		let cookies = Cookies {
+			preset: vec![],
+			jar: Some(Jar::default()),
+		};
+		assert_eq!(cookies.cookies(&url), None);
+	}
+
+	#[cfg(unix)]
+	#[tokio::test]
+	async fn relay_unix_socket_should_forward_requests() {
+		use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+		let path = std::env::temp_dir().join(format!("rey-{}.sock", std::process::id()));
+		let _ = std::fs::remove_file(&path);
+		let listener = tokio::net::UnixListener::bind(&path).unwrap();
+		tokio::spawn(async move {
+			while let Ok((mut stream, _)) = listener.accept().await {
+				let mut buf = [0_u8; 4096];
+				let _ = stream.read(&mut buf).await;
+				let _ = stream
+					.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok")
+					.await;
+			}
+		});
+		let addr = crate::client::relay_unix_socket(path.clone())
+			.await
+			.unwrap();
+		let body = reqwest::get(format!("http://{}/", addr))
+			.await
+			.unwrap()
+			.text()
+			.await
+			.unwrap();
+		assert_eq!(body, "ok");
+		std::fs::remove_file(&path).unwrap();
+		assert!(crate::client::relay_unix_socket(path).await.is_err());
+	}
+
+	#[test]
+	fn client_identity_should_report_unreadable_file() {
+		let err = Identity::try_from(ClientIdentity::Pem {
+			cert: PathBuf::from("/nonexistent/cert.pem"),
+			key: PathBuf::from("/nonexistent/key.pem"),
+		})
+		.unwrap_err();
+		assert_eq!(err.to_string(), "fail to read /nonexistent/cert.pem");
+	}
 
 	#[test]
 	fn try_into_header_should_work() {
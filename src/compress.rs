@@ -0,0 +1,105 @@
+use std::io::{Read, Write};
+
+use anyhow::{Context, Result};
+use serde::Serialize;
+
+/// Content encoding of the request body
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Compression {
+	Gzip,
+	#[value(name = "br")]
+	#[serde(rename = "br")]
+	Brotli,
+}
+
+impl Compression {
+	/// Value of the `Content-Encoding` header
+	pub fn encoding(&self) -> &'static str {
+		match self {
+			Compression::Gzip => "gzip",
+			Compression::Brotli => "br",
+		}
+	}
+
+	pub fn compress(&self, data: &[u8]) -> Result<Vec<u8>> {
+		match self {
+			Compression::Gzip => {
+				let mut encoder =
+					flate2::write::GzEncoder::new(vec![], flate2::Compression::default());
+				encoder.write_all(data)?;
+				encoder.finish()
+			}
+			Compression::Brotli => {
+				let mut encoder = brotli::CompressorWriter::new(vec![], 4096, 11, 22);
+				encoder.write_all(data)?;
+				encoder.flush()?;
+				Ok(encoder.into_inner())
+			}
+		}
+		.context("fail to compress the body")
+	}
+}
+
+/// Decode a body received with `Content-Encoding: encoding`, none when the
+/// encoding is unknown or the body is corrupted
+pub fn decompress(encoding: &str, data: &[u8]) -> Option<Vec<u8>> {
+	let mut decoded = vec![];
+	match encoding.trim() {
+		"gzip" => flate2::read::GzDecoder::new(data).read_to_end(&mut decoded),
+		"deflate" => flate2::read::ZlibDecoder::new(data).read_to_end(&mut decoded),
+		"br" => brotli::Decompressor::new(data, 4096).read_to_end(&mut decoded),
+		_ => return None,
+	}
+	.ok()?;
+	Some(decoded)
+}
+
+/// Size of the request body before and after compression
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct BodyCompression {
+	pub encoding: Compression,
+	pub original: u64,
+	pub compressed: u64,
+}
+
+#[cfg(test)]
+mod tests {
+	use std::io::Read;
+
+	use crate::compress::{decompress, Compression};
+
+	#[test]
+	fn compress_should_round_trip() {
+		let data = "hello world ".repeat(100);
+		let gzip = Compression::Gzip.compress(data.as_bytes()).unwrap();
+		assert!(gzip.len() < data.len());
+		let mut decoded = String::new();
+		flate2::read::GzDecoder::new(&gzip[..])
+			.read_to_string(&mut decoded)
+			.unwrap();
+		assert_eq!(decoded, data);
+
+		let br = Compression::Brotli.compress(data.as_bytes()).unwrap();
+		assert!(br.len() < data.len());
+		let mut decoded = String::new();
+		brotli::Decompressor::new(&br[..], 4096)
+			.read_to_string(&mut decoded)
+			.unwrap();
+		assert_eq!(decoded, data);
+	}
+
+	#[test]
+	fn decompress_should_decode_known_encodings() {
+		let data = b"hello world";
+		for compression in [Compression::Gzip, Compression::Brotli] {
+			let compressed = compression.compress(data).unwrap();
+			assert_eq!(
+				decompress(compression.encoding(), &compressed).unwrap(),
+				data
+			);
+		}
+		assert_eq!(decompress("zstd", data), None);
+		assert_eq!(decompress("gzip", data), None);
+	}
+}
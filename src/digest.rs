@@ -0,0 +1,156 @@
+use std::fmt::Write;
+
+use anyhow::{anyhow, Result};
+use rand::Rng;
+
+/// A `WWW-Authenticate: Digest ...` challenge, as described by RFC 2617.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Challenge {
+	realm: String,
+	nonce: String,
+	opaque: Option<String>,
+	/// Only `auth` is supported, `auth-int` isn't
+	qop: Option<String>,
+	/// `MD5` or `MD5-sess`
+	algorithm: String,
+}
+
+/// Split `key=value, key="quoted, value"` pairs.
+fn parse_params(s: &str) -> Vec<(String, String)> {
+	let mut params = vec![];
+	let mut rest = s.trim();
+	while let Some((key, tail)) = rest.split_once('=') {
+		let key = key
+			.trim()
+			.trim_start_matches(',')
+			.trim()
+			.to_ascii_lowercase();
+		let tail = tail.trim_start();
+		let (value, tail) = match tail.strip_prefix('"') {
+			Some(quoted) => {
+				let end = quoted.find('"').unwrap_or(quoted.len());
+				(&quoted[..end], quoted.get(end + 1..).unwrap_or(""))
+			}
+			None => {
+				let end = tail.find(',').unwrap_or(tail.len());
+				(tail[..end].trim(), &tail[end..])
+			}
+		};
+		params.push((key, value.to_string()));
+		rest = tail.trim_start().trim_start_matches(',');
+	}
+	params
+}
+
+fn md5(s: &str) -> String {
+	format!("{:x}", md5::compute(s))
+}
+
+impl Challenge {
+	pub fn parse(header: &str) -> Result<Challenge> {
+		let (scheme, params) = header.trim().split_once(' ').unwrap_or((header, ""));
+		if !scheme.eq_ignore_ascii_case("digest") {
+			return Err(anyhow!("not a digest challenge: {}", header));
+		}
+		let params = parse_params(params);
+		let param = |name: &str| {
+			params
+				.iter()
+				.find(|(key, _)| key == name)
+				.map(|(_, value)| value.clone())
+		};
+		let algorithm = param("algorithm").unwrap_or_else(|| "MD5".to_string());
+		if !algorithm.eq_ignore_ascii_case("md5") && !algorithm.eq_ignore_ascii_case("md5-sess") {
+			return Err(anyhow!("unsupported digest algorithm {}", algorithm));
+		}
+		let qop = match param("qop") {
+			Some(qop) if qop.split(',').any(|qop| qop.trim() == "auth") => Some("auth".to_string()),
+			Some(qop) => return Err(anyhow!("unsupported digest qop {}", qop)),
+			None => None,
+		};
+		Ok(Challenge {
+			realm: param("realm").unwrap_or_default(),
+			nonce: param("nonce").ok_or_else(|| anyhow!("digest challenge without nonce"))?,
+			opaque: param("opaque"),
+			qop,
+			algorithm,
+		})
+	}
+
+	/// The `Authorization` header answering the challenge for `method` on `uri`,
+	/// `uri` being the path and query of the url.
+	pub fn authorization(
+		&self,
+		username: &str,
+		password: &str,
+		method: &str,
+		uri: &str,
+		cnonce: &str,
+	) -> String {
+		let nc = "00000001";
+		let mut ha1 = md5(&format!("{}:{}:{}", username, self.realm, password));
+		if self.algorithm.eq_ignore_ascii_case("md5-sess") {
+			ha1 = md5(&format!("{}:{}:{}", ha1, self.nonce, cnonce));
+		}
+		let ha2 = md5(&format!("{}:{}", method, uri));
+		let response = match &self.qop {
+			Some(qop) => md5(&format!(
+				"{}:{}:{}:{}:{}:{}",
+				ha1, self.nonce, nc, cnonce, qop, ha2
+			)),
+			None => md5(&format!("{}:{}:{}", ha1, self.nonce, ha2)),
+		};
+		let mut header = format!(
+			r#"Digest username="{}", realm="{}", nonce="{}", uri="{}", algorithm={}, response="{}""#,
+			username, self.realm, self.nonce, uri, self.algorithm, response
+		);
+		if let Some(qop) = &self.qop {
+			write!(header, r#", qop={}, nc={}, cnonce="{}""#, qop, nc, cnonce).unwrap();
+		}
+		if let Some(opaque) = &self.opaque {
+			write!(header, r#", opaque="{}""#, opaque).unwrap();
+		}
+		header
+	}
+}
+
+/// A random client nonce
+pub fn cnonce<R: Rng>(rng: &mut R) -> String {
+	format!("{:016x}", rng.gen::<u64>())
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::digest::Challenge;
+
+	#[test]
+	fn authorization_should_match_rfc_example() {
+		let challenge = Challenge::parse(
+			r#"Digest realm="testrealm@host.com", qop="auth,auth-int", nonce="dcd98b7102dd2f0e8b11d0f600bfb0c093", opaque="5ccc069c403ebaf9f0171e9517f40e41""#,
+		)
+		.unwrap();
+		assert_eq!(
+			challenge.authorization(
+				"Mufasa",
+				"Circle Of Life",
+				"GET",
+				"/dir/index.html",
+				"0a4f113b"
+			),
+			r#"Digest username="Mufasa", realm="testrealm@host.com", nonce="dcd98b7102dd2f0e8b11d0f600bfb0c093", uri="/dir/index.html", algorithm=MD5, response="6629fae49393a05397450978507c4ef1", qop=auth, nc=00000001, cnonce="0a4f113b", opaque="5ccc069c403ebaf9f0171e9517f40e41""#
+		);
+	}
+
+	#[test]
+	fn parse_should_reject_unsupported_challenge() {
+		assert!(Challenge::parse(r#"Basic realm="x""#).is_err());
+		assert!(Challenge::parse(r#"Digest realm="x""#).is_err());
+		assert_eq!(
+			Challenge::parse(r#"Digest realm="x", nonce="n", algorithm=SHA-256"#)
+				.unwrap_err()
+				.to_string(),
+			"unsupported digest algorithm SHA-256"
+		);
+		assert!(Challenge::parse(r#"Digest nonce="n", qop="auth-int""#).is_err());
+	}
+}
@@ -0,0 +1,153 @@
+use std::fmt::Write;
+use std::time::{Duration, SystemTime};
+
+use anyhow::{anyhow, Result};
+use hdrhistogram::serialization::interval_log::IntervalLogWriterBuilder;
+use hdrhistogram::serialization::V2DeflateSerializer;
+use hdrhistogram::Histogram;
+
+use crate::report::Report;
+
+fn escape_label(value: &str) -> String {
+	value
+		.replace('\\', "\\\\")
+		.replace('"', "\\\"")
+		.replace('\n', "\\n")
+}
+
+/// Render the report in the Prometheus text exposition format, every metric
+/// labeled with the `host` requested.
+pub fn prometheus(report: &Report, host: &str) -> String {
+	let host = escape_label(host);
+	let mut text = String::new();
+	let mut gauge = |name: &str, help: &str, samples: &[(&str, f64)]| {
+		writeln!(text, "# HELP {} {}", name, help).unwrap();
+		writeln!(text, "# TYPE {} gauge", name).unwrap();
+		for (labels, value) in samples {
+			writeln!(text, "{}{{host=\"{}\"{}}} {}", name, host, labels, value).unwrap();
+		}
+	};
+	gauge(
+		"rey_requests",
+		"Requests sent.",
+		&[("", report.total_requests as f64)],
+	);
+	gauge(
+		"rey_requests_per_second",
+		"Requests sent per second.",
+		&[("", report.rps)],
+	);
+	gauge(
+		"rey_latency_seconds",
+		"Latency of successful requests.",
+		&[
+			(",quantile=\"0.5\"", report.p50),
+			(",quantile=\"0.9\"", report.p90),
+			(",quantile=\"0.99\"", report.p99),
+		],
+	);
+	gauge(
+		"rey_error_ratio",
+		"Fraction of the requests that failed.",
+		&[("", report.error_rate / 100_f64)],
+	);
+	gauge(
+		"rey_received_bytes",
+		"Body bytes received from successful requests.",
+		&[("", report.size_total as f64)],
+	);
+	text
+}
+
+/// Write the latencies, recorded in microseconds, as a HdrHistogram interval
+/// log holding a single interval of `total` from `start`. The max of the
+/// interval is in seconds.
+pub fn hdr_log<W: std::io::Write>(
+	writer: &mut W,
+	histogram: &Histogram<u64>,
+	start: SystemTime,
+	total: Duration,
+) -> Result<()> {
+	let mut serializer = V2DeflateSerializer::new();
+	let mut log = IntervalLogWriterBuilder::new()
+		.add_comment("Latencies of rey in microseconds")
+		.with_start_time(start)
+		.with_base_time(start)
+		.with_max_value_divisor(1_000_000_f64)
+		.begin_log_with(writer, &mut serializer)?;
+	log.write_histogram(histogram, Duration::ZERO, total, None)
+		.map_err(|err| anyhow!("fail to write histogram: {:?}", err))?;
+	writer.flush()?;
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use std::time::{Duration, SystemTime};
+
+	use hdrhistogram::serialization::interval_log::{IntervalLogIterator, LogEntry};
+	use hdrhistogram::Histogram;
+
+	use crate::export::{hdr_log, prometheus};
+	use crate::report::Report;
+
+	#[test]
+	fn prometheus_should_label_every_sample() {
+		let report = Report {
+			total_requests: 200,
+			num_res: 150,
+			error_rate: 25_f64,
+			rps: 40_f64,
+			p50: 0.25,
+			p90: 0.5,
+			p99: 1_f64,
+			size_total: 1024,
+			..Report::default()
+		};
+		let text = prometheus(&report, "localhost:8080");
+		assert!(text.contains("# TYPE rey_requests_per_second gauge\n"));
+		assert!(text.contains("rey_requests_per_second{host=\"localhost:8080\"} 40\n"));
+		assert!(
+			text.contains("rey_latency_seconds{host=\"localhost:8080\",quantile=\"0.9\"} 0.5\n")
+		);
+		assert!(text.contains("rey_error_ratio{host=\"localhost:8080\"} 0.25\n"));
+		assert!(text.contains("rey_received_bytes{host=\"localhost:8080\"} 1024\n"));
+		assert!(text
+			.lines()
+			.filter(|line| !line.starts_with('#'))
+			.all(|line| line.starts_with("rey_") && line.contains("host=")));
+	}
+
+	#[test]
+	fn prometheus_should_escape_host() {
+		let text = prometheus(&Report::default(), "a\"b");
+		assert!(text.contains("rey_requests{host=\"a\\\"b\"} 0\n"));
+	}
+
+	#[test]
+	fn hdr_log_should_write_one_interval() {
+		let mut histogram = Histogram::<u64>::new(3).unwrap();
+		for micros in [1_000, 2_000, 250_000] {
+			histogram.record(micros).unwrap();
+		}
+		let mut log = vec![];
+		hdr_log(
+			&mut log,
+			&histogram,
+			SystemTime::UNIX_EPOCH,
+			Duration::from_secs(2),
+		)
+		.unwrap();
+		let text = String::from_utf8(log.clone()).unwrap();
+		assert!(text.starts_with("#Latencies of rey in microseconds\n"));
+		let interval = IntervalLogIterator::new(&log)
+			.find_map(|entry| match entry.unwrap() {
+				LogEntry::Interval(interval) => Some(interval),
+				_ => None,
+			})
+			.unwrap();
+		assert_eq!(interval.duration(), Duration::from_secs(2));
+		assert!((interval.max() - 0.25).abs() < 0.001);
+		assert!(interval.encoded_histogram().starts_with("HIST"));
+	}
+}
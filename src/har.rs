@@ -0,0 +1,135 @@
+use anyhow::{Context, Result};
+use http::{HeaderMap, HeaderName, HeaderValue, Method};
+use reqwest::Url;
+use serde::Deserialize;
+
+use crate::work::Target;
+
+#[derive(Debug, Deserialize)]
+struct Har {
+	log: Log,
+}
+
+#[derive(Debug, Deserialize)]
+struct Log {
+	entries: Vec<Entry>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Entry {
+	request: Request,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Request {
+	method: String,
+	url: String,
+	#[serde(default)]
+	headers: Vec<Header>,
+	post_data: Option<PostData>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Header {
+	name: String,
+	value: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct PostData {
+	#[serde(default)]
+	text: String,
+}
+
+/// Headers recorded by the browser that the client computes itself, along
+/// with HTTP/2 pseudo headers like ":authority".
+pub(crate) fn skip_header(name: &str) -> bool {
+	name.starts_with(':')
+		|| name.eq_ignore_ascii_case("content-length")
+		|| name.eq_ignore_ascii_case("connection")
+}
+
+impl TryFrom<Request> for Target<Vec<u8>> {
+	type Error = anyhow::Error;
+
+	fn try_from(request: Request) -> Result<Self> {
+		let method = Method::from_bytes(request.method.as_bytes())
+			.with_context(|| format!("invalid method: {}", request.method))?;
+		let url = request
+			.url
+			.parse::<Url>()
+			.with_context(|| format!("invalid url: {}", request.url))?;
+		let mut headers = HeaderMap::new();
+		for header in request.headers.iter().filter(|h| !skip_header(&h.name)) {
+			let name = HeaderName::try_from(header.name.as_str())
+				.with_context(|| format!("invalid header name: {}", header.name))?;
+			let value = HeaderValue::try_from(header.value.as_str())
+				.with_context(|| format!("invalid value of header {}", header.name))?;
+			headers.append(name, value);
+		}
+		let body = request
+			.post_data
+			.map(|data| data.text.into_bytes())
+			.unwrap_or_default();
+		Ok(Target {
+			method,
+			url,
+			headers,
+			body,
+		})
+	}
+}
+
+/// Parse the requests recorded in the `log.entries` of a HAR file, in order.
+pub fn parse_har(content: &str) -> Result<Vec<Target<Vec<u8>>>> {
+	let har: Har = serde_json::from_str(content).context("invalid HAR file")?;
+	har.log
+		.entries
+		.into_iter()
+		.enumerate()
+		.map(|(i, entry)| {
+			Target::try_from(entry.request).with_context(|| format!("invalid HAR entry {}", i))
+		})
+		.collect()
+}
+
+#[cfg(test)]
+mod tests {
+	use http::Method;
+
+	use crate::har::parse_har;
+
+	#[test]
+	fn parse_har_should_work() {
+		let targets = parse_har(
+			r#"{"log": {"entries": [
+				{"request": {"method": "GET", "url": "http://localhost/a", "headers": [
+					{"name": ":authority", "value": "localhost"},
+					{"name": "Accept", "value": "text/html"}
+				]}},
+				{"request": {"method": "POST", "url": "http://localhost/b", "headers": [
+					{"name": "Content-Length", "value": "7"}
+				], "postData": {"mimeType": "application/json", "text": "{\"a\":1}"}}}
+			]}}"#,
+		)
+		.unwrap();
+		assert_eq!(targets.len(), 2);
+		assert_eq!(targets[0].method, Method::GET);
+		assert_eq!(targets[0].url.as_str(), "http://localhost/a");
+		assert_eq!(targets[0].headers.len(), 1);
+		assert_eq!(targets[0].headers.get("accept").unwrap(), "text/html");
+		assert!(targets[0].body.is_empty());
+		assert_eq!(targets[1].method, Method::POST);
+		assert!(targets[1].headers.is_empty());
+		assert_eq!(targets[1].body, br#"{"a":1}"#);
+	}
+
+	#[test]
+	fn parse_har_should_report_entry() {
+		let err =
+			parse_har(r#"{"log": {"entries": [{"request": {"method": "GET", "url": "x"}}]}}"#)
+				.unwrap_err();
+		assert_eq!(err.to_string(), "invalid HAR entry 0");
+	}
+}
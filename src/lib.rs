@@ -1,4 +1,51 @@
 pub mod arg;
+pub mod body_dir;
 pub mod client;
+pub mod compress;
+pub mod digest;
+pub mod export;
+pub mod har;
+pub mod mask;
+pub mod multipart;
+pub mod plan;
+pub mod rate;
+pub mod raw;
 pub mod report;
+pub mod sla;
+pub mod template;
 pub mod work;
+
+use std::sync::Arc;
+
+use reqwest::{Body, Client};
+use tokio::sync::Notify;
+
+use crate::report::Reporter;
+use crate::work::Work;
+
+/// Run the work until every request was sent, or its duration elapsed
+///
+/// ```no_run
+/// # async fn example() -> anyhow::Result<()> {
+/// let work = rey::work::WorkBuilder::new()
+///     .url("http://localhost:8080/".parse()?)
+///     .workers(10)
+///     .total_requests(1000)
+///     .build()?;
+/// let start = std::time::Instant::now();
+/// let reporter = rey::run(work).await?;
+/// let report = reporter.into_report(start.elapsed(), &[50_f64, 99_f64]);
+/// println!("{} requests/sec, p99 {} secs", report.rps, report.p99);
+/// for dist in &report.latency_dist {
+///     println!("{}% in {} secs", dist.percentage, dist.latency);
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub async fn run<C, B>(work: Work<C, B>) -> anyhow::Result<Reporter>
+where
+	C: TryInto<Client, Error = anyhow::Error> + Clone,
+	B: Into<Body> + Clone + Send + Sync + 'static,
+{
+	work.execute(Arc::new(Notify::new())).await
+}
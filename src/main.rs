@@ -1,16 +1,39 @@
-use std::sync::Arc;
-use std::time::{Duration, Instant};
+use std::fs::File;
+use std::io::{BufWriter, IsTerminal, Write};
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
 
 use anyhow::{anyhow, Context};
+use bytes::Bytes;
 use clap::Parser;
 use flexi_logger::{FlexiLoggerError, Logger};
+use http::header::{CONTENT_ENCODING, HOST};
+use http::{HeaderMap, HeaderValue};
 use reqwest::Url;
+use tokio::io::AsyncReadExt;
 use tokio::signal::ctrl_c;
 use tokio::sync::Notify;
 
-use rey::arg::Args;
-use rey::client::ClientBuilder;
-use rey::work::Work;
+use rey::arg::{
+	args_with_config, parse_url_file, parse_user_agent_file, resolve_for_urls, Args, OutputFormat,
+	UrlSpec,
+};
+use rey::body_dir::BodyDir;
+use rey::client::{relay_unix_socket, ClientBuilder, ClientIdentity, IpFamily};
+use rey::compress::BodyCompression;
+use rey::export::{hdr_log, prometheus};
+use rey::har::parse_har;
+use rey::mask::HeaderMask;
+use rey::multipart::MultipartForm;
+use rey::plan::plan;
+use rey::raw::parse_raw_request;
+use rey::report::{RunSummary, Runs};
+use rey::sla::Sla;
+use rey::template::BodyTemplate;
+use rey::work::{BasicAuth, Expect, Retry, Target, WorkBuilder};
 
 macro_rules! unwrap_or_exit {
 	($expr:expr) => {
@@ -27,10 +50,36 @@ macro_rules! unwrap_or_exit {
 #[tokio::main]
 async fn main() {
 	unwrap_or_exit!(init_logger().context("fail to statup logger"));
-	let args = Args::parse();
+	let argv = unwrap_or_exit!(args_with_config(std::env::args_os().collect()));
+	let args = Args::parse_from(argv);
+	// these record single requests, the runs of --repeat would be mixed or lost
+	if args.repeat > 1
+		&& (args.events_jsonl.is_some() || args.dump_csv.is_some() || args.hdr_file.is_some())
+	{
+		eprintln!(
+			"--events-jsonl, --dump-csv and --hdr-file only record a single run, drop --repeat"
+		);
+		std::process::exit(1);
+	}
+	let mut body_file = None;
 	let body: Vec<u8>;
-	if let Some(body_str) = args.body {
+	if args.stream_body {
+		let file = args.body_file.expect("--stream-body requires -D");
+		if file == Path::new("-") {
+			eprintln!("can't stream the body from stdin");
+			std::process::exit(1);
+		}
+		body_file = Some(file);
+		body = vec![];
+	} else if let Some(body_str) = args.body {
 		body = body_str.into_bytes();
+	} else if args.body_stdin || args.body_file.as_deref() == Some(Path::new("-")) {
+		let mut buf = vec![];
+		unwrap_or_exit!(tokio::io::stdin()
+			.read_to_end(&mut buf)
+			.await
+			.context("fail to read body from stdin"));
+		body = buf;
 	} else if let Some(file) = args.body_file {
 		body = unwrap_or_exit!(tokio::fs::read(file)
 			.await
@@ -38,50 +87,443 @@ async fn main() {
 	} else {
 		body = vec![];
 	}
-	let body: &'static [u8] = Box::leak(body.into_boxed_slice());
+	let body_dir = args
+		.body_dir
+		.as_deref()
+		.map(|dir| unwrap_or_exit!(BodyDir::load(dir)));
+	let user_agents = match &args.user_agent_file {
+		Some(file) => unwrap_or_exit!(std::fs::read_to_string(file)
+			.context("invalid user agent file")
+			.and_then(|content| parse_user_agent_file(&content))),
+		None => vec![],
+	};
+	let mut template = None;
+	if args.body_template {
+		let string =
+			unwrap_or_exit!(std::str::from_utf8(&body).context("body template is not UTF-8"));
+		let parsed = unwrap_or_exit!(BodyTemplate::parse(string).context("invalid body template"));
+		if parsed.has_tokens() {
+			template = Some(parsed);
+		}
+	}
+	if args.json && template.is_none() && !body.is_empty() {
+		unwrap_or_exit!(
+			serde_json::from_slice::<serde_json::Value>(&body).context("body is not valid JSON")
+		);
+	}
+	let mut multipart = None;
+	if !args.form_fields.is_empty() || !args.form_files.is_empty() {
+		let mut form = MultipartForm::default();
+		for (name, value) in args.form_fields {
+			form = form.field(name, value);
+		}
+		for (name, path) in args.form_files {
+			form = unwrap_or_exit!(form.file(name, &path));
+		}
+		multipart = Some(form);
+	}
+	let mut body_compression = None;
+	// requests without a body send neither a compressed empty one nor Content-Encoding
+	let body = match args.compress {
+		Some(compression) if !body.is_empty() => {
+			let compressed = unwrap_or_exit!(compression.compress(&body));
+			body_compression = Some(BodyCompression {
+				encoding: compression,
+				original: body.len() as u64,
+				compressed: compressed.len() as u64,
+			});
+			compressed
+		}
+		_ => body,
+	};
+	// shared by every request without copying
+	let body = Bytes::from(body);
+	let mut weights = None;
+	let mut targets: Vec<Target<Bytes>> = if let Some(file) = args.har {
+		let content = unwrap_or_exit!(tokio::fs::read_to_string(file)
+			.await
+			.context("invalid HAR file"));
+		unwrap_or_exit!(parse_har(&content))
+			.into_iter()
+			.map(|target| Target {
+				method: target.method,
+				url: target.url,
+				headers: target.headers,
+				body: Bytes::from(target.body),
+			})
+			.collect()
+	} else if let Some(file) = args.raw_request {
+		let content = unwrap_or_exit!(tokio::fs::read(file)
+			.await
+			.context("invalid raw request file"));
+		let scheme = if args.raw_https { "https" } else { "http" };
+		let target = unwrap_or_exit!(parse_raw_request(&content, scheme));
+		vec![Target {
+			method: target.method,
+			url: target.url,
+			headers: target.headers,
+			body: Bytes::from(target.body),
+		}]
+	} else {
+		let method = args.method_raw.clone().unwrap_or(args.method.clone());
+		let mut specs = unwrap_or_exit!(args
+			.urls
+			.iter()
+			.map(|url| url
+				.parse::<Url>()
+				.map(UrlSpec::from)
+				.with_context(|| format!("invalid url: {}", url)))
+			.collect::<anyhow::Result<Vec<UrlSpec>>>());
+		if let Some(file) = args.url_file {
+			let content = unwrap_or_exit!(tokio::fs::read_to_string(file)
+				.await
+				.context("invalid url file"));
+			specs.extend(unwrap_or_exit!(parse_url_file(&content)));
+		}
+		if !args.url_weighted.is_empty() {
+			let mut list = vec![1; specs.len()];
+			for (url, weight) in args.url_weighted {
+				specs.push(UrlSpec::from(url));
+				list.push(weight);
+			}
+			weights = Some(list);
+		}
+		specs
+			.into_iter()
+			.map(|spec| Target {
+				method: spec.method.unwrap_or_else(|| method.clone()),
+				url: spec.url,
+				headers: HeaderMap::new(),
+				body: match spec.body {
+					Some(spec_body) => {
+						let mut spec_body = spec_body.into_bytes();
+						if let Some(compression) = args.compress.filter(|_| !spec_body.is_empty()) {
+							spec_body = unwrap_or_exit!(compression.compress(&spec_body));
+						}
+						Bytes::from(spec_body)
+					}
+					None => body.clone(),
+				},
+			})
+			.collect()
+	};
+	if let Some(compression) = args.compress {
+		for target in targets.iter_mut().filter(|target| !target.body.is_empty()) {
+			target.headers.insert(
+				CONTENT_ENCODING,
+				HeaderValue::from_static(compression.encoding()),
+			);
+		}
+	}
+	let unix_socket = args.unix.as_ref().map(|path| path.display().to_string());
+	if let Some(path) = args.unix {
+		let relay = unwrap_or_exit!(relay_unix_socket(path).await);
+		for target in &mut targets {
+			if target.url.scheme() != "http" {
+				unwrap_or_exit!(Err(anyhow!(
+					"only http urls can be requested over a unix socket: {}",
+					target.url
+				)));
+			}
+			let host = match target.url.port() {
+				Some(port) => format!("{}:{}", target.url.host_str().unwrap_or_default(), port),
+				None => target.url.host_str().unwrap_or_default().to_string(),
+			};
+			if args.host.is_none() && !target.headers.contains_key(HOST) {
+				target.headers.insert(
+					HOST,
+					unwrap_or_exit!(HeaderValue::try_from(host).context("invalid host")),
+				);
+			}
+			let _ = target.url.set_ip_host(relay.ip());
+			let _ = target.url.set_port(Some(relay.port()));
+		}
+	}
+	let mut resolve = unwrap_or_exit!(resolve_for_urls(
+		args.resolve,
+		targets.iter().map(|target| &target.url)
+	));
+	if let Some(sni) = args.sni {
+		for target in &mut targets {
+			let host = target.url.host_str().unwrap_or_default();
+			let ip = match host.trim_start_matches('[').trim_end_matches(']').parse() {
+				Ok(ip) => ip,
+				Err(_) => unwrap_or_exit!(Err(anyhow!(
+					"--sni needs urls with an IP address, use --resolve for {}",
+					target.url
+				))),
+			};
+			// the server name resolves to one address, whatever the url
+			if let Some((_, other)) = resolve
+				.iter()
+				.find(|(host, addr)| host == &sni && addr.ip() != ip)
+			{
+				unwrap_or_exit!(Err(anyhow!(
+					"--sni can't send {} to both {} and {}, use urls with one IP address",
+					sni,
+					other.ip(),
+					ip
+				)));
+			}
+			// the port of the address is ignored, the url keeps its own
+			let entry = (sni.clone(), SocketAddr::new(ip, 0));
+			if !resolve.contains(&entry) {
+				resolve.push(entry);
+			}
+			let _ = target.url.set_host(Some(&sni));
+		}
+	}
 	let client_builder = ClientBuilder {
 		headers: args.headers,
-		timeout: if args.timeout > 0 {
-			Some(Duration::from_secs(args.timeout))
+		timeout: Some(Duration::from_secs(args.timeout)).filter(|timeout| !timeout.is_zero()),
+		connect_timeout: Some(Duration::from_secs(
+			args.connect_timeout.unwrap_or(args.timeout),
+		))
+		.filter(|timeout| !timeout.is_zero()),
+		wire_size: args.report_wire_size,
+		content_type: if args.form {
+			HeaderValue::from_static("application/x-www-form-urlencoded")
+		} else if args.json {
+			HeaderValue::from_static("application/json")
 		} else {
-			None
+			args.content_type_header
 		},
-		content_type: args.content_type_header,
 		accept: args.accept_header,
 		user_agent: args.user_agent_header,
 		proxy: args.proxy_address,
+		proxy_auth: args.proxy_auth,
 		host: args.host,
 		disable_redirect: args.disable_redirect,
+		max_redirects: args.max_redirects,
+		disable_keepalive: args.disable_keepalive,
+		max_connections: args.max_connections.map(|max| max as usize),
+		http_version: args.http_version,
+		insecure: args.insecure,
+		local_address: None,
+		identity: match (args.cert, args.key, args.pkcs12) {
+			(Some(cert), Some(key), _) => Some(ClientIdentity::Pem { cert, key }),
+			(_, _, Some(file)) => Some(ClientIdentity::Pkcs12 {
+				file,
+				password: args.pass,
+			}),
+			_ => None,
+		},
+		ip_family: match (args.ipv4, args.ipv6) {
+			(true, _) => Some(IpFamily::V4),
+			(_, true) => Some(IpFamily::V6),
+			_ => None,
+		},
+		resolve,
+		cookies: args.cookies,
+		cookie_jar: args.cookie_jar,
 	};
-	let work = Work {
-		client_builder,
-		url: unwrap_or_exit!(args.url.parse::<Url>().context("invalid url")),
-		method: args.method,
-		workers: args.workers,
-		auth: args.basic_auth,
-		total_requests: args.requests,
-		rate_limit: args.rate_limit,
-		body,
-	};
+	let mut hosts: Vec<String> = vec![];
+	for target in &targets {
+		let host = target.url.host_str().unwrap_or_default().to_string();
+		if !hosts.contains(&host) {
+			hosts.push(host);
+		}
+	}
+	// the events own stdout then, the report and the progress bar keep off it
+	let events_to_stdout = args.events_jsonl.as_deref() == Some(Path::new("-"));
+	let events = args
+		.events_jsonl
+		.map(|path| -> Arc<Mutex<dyn Write + Send>> {
+			if path == Path::new("-") {
+				Arc::new(Mutex::new(BufWriter::new(std::io::stdout())))
+			} else {
+				let file =
+					unwrap_or_exit!(File::create(&path).context("fail to create events file"));
+				Arc::new(Mutex::new(BufWriter::new(file)))
+			}
+		});
+	// the builder names its fields, the user knows the flags
+	if args.workers == 0 {
+		eprintln!("-c must be at least one");
+		std::process::exit(1);
+	}
+	if args.max_duration.is_none() && args.requests < args.workers as u64 {
+		eprintln!(
+			"{} requests can't keep {} workers busy, lower -c or raise -n",
+			args.requests, args.workers
+		);
+		std::process::exit(1);
+	}
+	let work = unwrap_or_exit!(WorkBuilder::new()
+		.client(client_builder)
+		.client_per_worker(args.client_per_worker)
+		.local_addresses(args.local_addrs)
+		.user_agents(user_agents)
+		.targets(targets)
+		.template(template)
+		.multipart(multipart)
+		.body_file(body_file)
+		.body_dir(body_dir)
+		.weights(weights)
+		.workers(args.workers)
+		.max_connections(args.max_connections.map(|max| max as usize))
+		.requests_per_conn(args.requests_per_conn)
+		.result_buffer(args.result_buffer)
+		.auth(args.basic_auth.map(|auth| BasicAuth {
+			digest: args.digest,
+			..auth
+		}))
+		.bearer(args.bearer)
+		.version(args.http_version.map(|version| version.version()))
+		.cache_bust(args.cache_bust.then_some(args.cache_bust_param))
+		.total_requests(args.requests)
+		// -n is ignored with -z, --max-requests caps the run instead
+		.duration(args.max_duration)
+		.max_requests(args.max_requests)
+		.rate_limit(args.rate_limit)
+		.global_rate_limit(args.global_rate_limit)
+		.open_loop(args.open_loop)
+		.correct_co(args.correct_co)
+		.think(args.think)
+		.think_jitter(args.think_jitter)
+		.retry(Retry {
+			retries: args.retries,
+			backoff: args.retry_backoff,
+			on_5xx: args.retry_5xx,
+		})
+		.expect(Expect {
+			status: args.expect_status,
+			body: args.expect_body,
+			body_regex: args.expect_body_regex,
+		})
+		.skip_body(args.no_body_read)
+		.ttfb_only(args.no_body)
+		.etag(args.etag)
+		.ramp_up(args.ramp_up)
+		.warmup(args.warmup)
+		.warmup_requests(args.warmup_requests)
+		.drain_timeout(args.drain_timeout)
+		.max_errors(args.max_errors)
+		.slow_threshold(args.slow_threshold)
+		.reuse_threshold(Duration::from_millis(args.reuse_threshold))
+		.verbose_errors(args.verbose_errors)
+		.progress(!args.no_progress && !events_to_stdout && std::io::stdout().is_terminal())
+		.records(args.dump_csv.is_some())
+		.timeline(args.timeline)
+		.per_worker(args.per_worker)
+		.throttle_status(args.throttle_status)
+		.events(events)
+		.hdr(args.hdr_file.is_some())
+		.streaming(args.streaming)
+		.seed(args.seed)
+		.build());
+	if args.dry_run {
+		let mask = HeaderMask::new(args.mask_headers);
+		print!("{}", unwrap_or_exit!(plan(&work, &mask)));
+		return;
+	}
 	let notify = Arc::new(Notify::new());
 	let cancel = notify.clone();
-	// todo: instead by pending()
-	let max_duration = args
-		.max_duration
-		.unwrap_or(Duration::from_secs(60 * 60 * 24));
+	let output = args.output;
+	let percentiles = args.percentiles;
+	let sla = Sla {
+		max_p99: args.fail_if_p99,
+		max_error_rate: args.fail_if_error_rate,
+		min_rps: args.fail_if_rps_below,
+	};
+	let interrupted = Arc::new(AtomicBool::new(false));
+	let interrupt = interrupted.clone();
 	tokio::spawn(async move {
-		tokio::select! {
-			_ = tokio::time::sleep(max_duration)=>{}
-			_ = ctrl_c()=>{}
-		}
+		let _ = ctrl_c().await;
+		interrupt.store(true, Ordering::Relaxed);
 		notify.notify_one();
 	});
 
-	// execute
-	let start = Instant::now();
-	let report = unwrap_or_exit!(work.execute(cancel).await);
-	let reporter = report.into_report(start.elapsed());
-	reporter.print();
+	// execute, every run but the last on a copy of the work
+	let mut runs = vec![];
+	let mut work = Some(work);
+	let (mut report, total, started) = loop {
+		let last = runs.len() + 1 >= args.repeat as usize;
+		let run = if last { work.take() } else { work.clone() };
+		let run = run.expect("only the last run takes the work");
+		let start = Instant::now();
+		let started = SystemTime::now();
+		let report = unwrap_or_exit!(run.execute(cancel.clone()).await);
+		let total = start.elapsed() - report.warmup_elapsed;
+		// an interrupted run is the last one
+		if last || interrupted.load(Ordering::Relaxed) {
+			break (report, total, started);
+		}
+		let report = report.into_report(total, &percentiles);
+		runs.push(RunSummary {
+			rps: report.rps,
+			p99: report.p99,
+		});
+	};
+	if let Some(path) = args.dump_csv {
+		let file = unwrap_or_exit!(File::create(&path).context("fail to create csv file"));
+		unwrap_or_exit!(report
+			.write_csv(BufWriter::new(file))
+			.context("fail to write csv file"));
+	}
+	report.histogram_scale = args.histogram_scale;
+	let hdr_histogram = report.hdr_histogram.take();
+	let mut reporter = report.into_report(total, &percentiles);
+	reporter.http_version = args.http_version.map(|version| version.to_string());
+	reporter.unix_socket = unix_socket;
+	reporter.ramp_up = args.ramp_up;
+	reporter.workers = args.workers;
+	reporter.max_connections = args.max_connections;
+	reporter.requests_per_conn = args.requests_per_conn;
+	reporter.open_loop = args.open_loop;
+	reporter.correct_co = args.correct_co;
+	reporter.body_compression = body_compression;
+	reporter.wire_size = args.report_wire_size;
+	reporter.verbose = args.verbose;
+	reporter.quiet = args.quiet;
+	if args.repeat > 1 {
+		runs.push(RunSummary {
+			rps: reporter.rps,
+			p99: reporter.p99,
+		});
+		reporter.runs = Some(Runs::new(runs));
+	}
+	if events_to_stdout {
+		eprintln!(
+			"{}",
+			match output {
+				OutputFormat::Text => reporter.render(),
+				OutputFormat::Json => reporter.render_json(),
+				OutputFormat::Markdown => reporter.render_markdown(),
+			}
+		);
+	} else {
+		match output {
+			OutputFormat::Text => reporter.print(),
+			OutputFormat::Json => reporter.print_json(),
+			OutputFormat::Markdown => reporter.print_markdown(),
+		}
+	}
+	if let Some(path) = args.report_file {
+		unwrap_or_exit!(
+			std::fs::write(&path, reporter.render()).context("fail to write report file")
+		);
+	}
+	if let (Some(path), Some(histogram)) = (args.hdr_file, hdr_histogram) {
+		let file = unwrap_or_exit!(File::create(&path).context("fail to create hdr file"));
+		unwrap_or_exit!(
+			hdr_log(&mut BufWriter::new(file), &histogram, started, total)
+				.context("fail to write hdr file")
+		);
+	}
+	if let Some(path) = args.prometheus_file {
+		unwrap_or_exit!(
+			std::fs::write(&path, prometheus(&reporter, &hosts.join(",")))
+				.context("fail to write prometheus file")
+		);
+	}
+	let violations = sla.violations(&reporter);
+	if !violations.is_empty() {
+		for violation in violations {
+			eprintln!("SLA violated: {}", violation);
+		}
+		std::process::exit(1);
+	}
 }
 
 fn init_logger() -> Result<(), FlexiLoggerError> {
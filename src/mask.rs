@@ -0,0 +1,61 @@
+use std::borrow::Cow;
+
+use http::header::{AUTHORIZATION, COOKIE, PROXY_AUTHORIZATION};
+use http::{HeaderName, HeaderValue};
+
+/// Shown instead of the value of a masked header
+pub const MASK: &str = "***";
+
+/// Headers whose values are hidden wherever headers are displayed, requests
+/// still send the real values.
+#[derive(Debug, Clone, Default)]
+pub struct HeaderMask {
+	/// Masked on top of `Authorization`, `Cookie` and `Proxy-Authorization`
+	pub names: Vec<HeaderName>,
+}
+
+impl HeaderMask {
+	pub fn new(names: Vec<HeaderName>) -> Self {
+		HeaderMask { names }
+	}
+
+	/// The value of a header as displayed.
+	pub fn mask_header<'a>(&self, name: &HeaderName, value: &'a HeaderValue) -> Cow<'a, str> {
+		if [AUTHORIZATION, COOKIE, PROXY_AUTHORIZATION].contains(name) || self.names.contains(name)
+		{
+			Cow::Borrowed(MASK)
+		} else {
+			String::from_utf8_lossy(value.as_bytes())
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use http::{HeaderName, HeaderValue};
+
+	use crate::mask::HeaderMask;
+
+	#[test]
+	fn mask_header_should_hide_secrets() {
+		let mask = HeaderMask::new(vec![HeaderName::from_static("x-api-key")]);
+		let value = HeaderValue::from_static("secret");
+		for name in [
+			"authorization",
+			"cookie",
+			"proxy-authorization",
+			"x-api-key",
+		] {
+			assert_eq!(
+				mask.mask_header(&HeaderName::from_static(name), &value),
+				"***"
+			);
+		}
+		let name = HeaderName::from_static("accept");
+		assert_eq!(mask.mask_header(&name, &value), "secret");
+		assert_eq!(
+			HeaderMask::default().mask_header(&HeaderName::from_static("x-api-key"), &value),
+			"secret"
+		);
+	}
+}
@@ -0,0 +1,82 @@
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use reqwest::multipart::{Form, Part};
+
+#[derive(Debug, Clone, PartialEq)]
+struct FormFile {
+	name: String,
+	file_name: String,
+	content: Vec<u8>,
+}
+
+/// A multipart/form-data body, rebuilt for every request since a `Form` is
+/// consumed by sending it. Files are read once upfront.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MultipartForm {
+	fields: Vec<(String, String)>,
+	files: Vec<FormFile>,
+}
+
+impl MultipartForm {
+	pub fn field(mut self, name: String, value: String) -> Self {
+		self.fields.push((name, value));
+		self
+	}
+
+	pub fn file(mut self, name: String, path: &Path) -> Result<Self> {
+		let content =
+			std::fs::read(path).with_context(|| format!("fail to read {}", path.display()))?;
+		let file_name = path
+			.file_name()
+			.map(|file_name| file_name.to_string_lossy().into_owned())
+			.unwrap_or_default();
+		self.files.push(FormFile {
+			name,
+			file_name,
+			content,
+		});
+		Ok(self)
+	}
+
+	pub fn build(&self) -> Form {
+		let mut form = Form::new();
+		for (name, value) in &self.fields {
+			form = form.text(name.clone(), value.clone());
+		}
+		for file in &self.files {
+			let part = Part::bytes(file.content.clone()).file_name(file.file_name.clone());
+			form = form.part(file.name.clone(), part);
+		}
+		form
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::path::Path;
+
+	use crate::multipart::MultipartForm;
+
+	#[test]
+	fn file_should_report_unreadable_file() {
+		let err = MultipartForm::default()
+			.file("image".to_string(), Path::new("/nonexistent/a.png"))
+			.unwrap_err();
+		assert_eq!(err.to_string(), "fail to read /nonexistent/a.png");
+	}
+
+	#[test]
+	fn build_should_include_fields_and_files() {
+		let path = std::env::temp_dir().join("rey-multipart-test.txt");
+		std::fs::write(&path, "hello").unwrap();
+		let form = MultipartForm::default()
+			.field("title".to_string(), "cat".to_string())
+			.file("image".to_string(), &path)
+			.unwrap();
+		std::fs::remove_file(&path).unwrap();
+		assert_eq!(form.files[0].file_name, "rey-multipart-test.txt");
+		assert_eq!(form.files[0].content, b"hello");
+		assert!(!form.build().boundary().is_empty());
+	}
+}
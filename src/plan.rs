@@ -0,0 +1,137 @@
+use std::fmt::Write;
+
+use anyhow::Result;
+use http::header::{AUTHORIZATION, COOKIE, PROXY_AUTHORIZATION, USER_AGENT};
+use http::HeaderValue;
+use reqwest::Body;
+
+use crate::client::ClientBuilder;
+use crate::mask::{HeaderMask, MASK};
+use crate::work::Work;
+
+/// Describe what the work would send, without sending anything.
+pub fn plan<B>(work: &Work<ClientBuilder, B>, mask: &HeaderMask) -> Result<String>
+where
+	B: Into<Body> + Clone + Send + Sync + 'static,
+{
+	let mut plan = String::new();
+	for target in &work.targets {
+		writeln!(plan, "Request: {} {}", target.method, target.url)?;
+	}
+	writeln!(plan, "Headers:")?;
+	let mut headers = work.client_builder.default_headers()?;
+	for target in &work.targets {
+		headers.extend(target.headers.clone());
+	}
+	// rotated User-Agents take turns from the first
+	if let Some(user_agent) = work.user_agents.first() {
+		headers.insert(USER_AGENT, user_agent.clone());
+	}
+	if work.auth.as_ref().is_some_and(|auth| !auth.digest) || work.bearer.is_some() {
+		headers.insert(AUTHORIZATION, HeaderValue::from_static(MASK));
+	}
+	if !work.client_builder.cookies.is_empty() {
+		headers.insert(COOKIE, HeaderValue::from_static(MASK));
+	}
+	let proxy = work.client_builder.proxy.as_ref();
+	if work.client_builder.proxy_auth.is_some()
+		|| proxy.is_some_and(|url| !url.username().is_empty())
+	{
+		headers.insert(PROXY_AUTHORIZATION, HeaderValue::from_static(MASK));
+	}
+	for (name, value) in &headers {
+		writeln!(plan, "  {}: {}", name, mask.mask_header(name, value))?;
+	}
+	match work.duration {
+		Some(duration) => writeln!(plan, "Duration: {:?}", duration)?,
+		None => writeln!(plan, "Total requests: {}", work.total_requests)?,
+	}
+	writeln!(plan, "Workers: {}", work.workers)?;
+	if work.duration.is_none() {
+		let workers = work.workers as u64;
+		let (min, max) = (
+			work.total_requests / workers,
+			work.total_requests.div_ceil(workers),
+		);
+		if min == max {
+			writeln!(plan, "Requests per worker: {}", min)?;
+		} else {
+			writeln!(plan, "Requests per worker: {} to {}", min, max)?;
+		}
+	}
+	match (work.global_rate_limit, work.rate_limit) {
+		(Some(qps), _) => writeln!(plan, "Rate limit: {} req/s shared by all workers", qps)?,
+		(None, Some(qps)) => writeln!(plan, "Rate limit: {} req/s per worker", qps)?,
+		(None, None) => writeln!(plan, "Rate limit: none")?,
+	}
+	match work.client_builder.timeout {
+		Some(timeout) => writeln!(plan, "Timeout: {:?}", timeout)?,
+		None => writeln!(plan, "Timeout: none")?,
+	}
+	Ok(plan)
+}
+
+#[cfg(test)]
+mod tests {
+	use std::time::Duration;
+
+	use http::HeaderName;
+
+	use crate::client::ClientBuilder;
+	use crate::mask::HeaderMask;
+	use crate::plan::plan;
+	use crate::work::WorkBuilder;
+
+	#[test]
+	fn plan_should_mask_secrets() {
+		let work = WorkBuilder::new()
+			.client(ClientBuilder {
+				headers: vec![
+					"Authorization: Basic cm9vdDpyb290".to_string(),
+					"X-Api-Key: secret".to_string(),
+				],
+				cookies: vec!["session=abc".to_string()],
+				proxy_auth: Some(("user".to_string(), "pass".to_string())),
+				..ClientBuilder::default()
+			})
+			.url("http://localhost/".parse().unwrap())
+			.workers(3)
+			.total_requests(10)
+			.rate_limit(5_f64)
+			.build()
+			.unwrap();
+		assert_eq!(
+			plan(
+				&work,
+				&HeaderMask::new(vec![HeaderName::from_static("x-api-key")])
+			)
+			.unwrap(),
+			"Request: GET http://localhost/
+Headers:
+  authorization: ***
+  x-api-key: ***
+  content-type: text/html
+  user-agent: rey/0.1.0
+  cookie: ***
+  proxy-authorization: ***
+Total requests: 10
+Workers: 3
+Requests per worker: 3 to 4
+Rate limit: 5 req/s per worker
+Timeout: 20s
+"
+		);
+	}
+
+	#[test]
+	fn plan_should_show_duration() {
+		let work = WorkBuilder::new()
+			.url("http://localhost/".parse().unwrap())
+			.duration(Duration::from_secs(10))
+			.global_rate_limit(100_f64)
+			.build()
+			.unwrap();
+		let plan = plan(&work, &HeaderMask::default()).unwrap();
+		assert!(plan.contains("Duration: 10s\nWorkers: 50\nRate limit: 100 req/s shared"));
+	}
+}
@@ -0,0 +1,50 @@
+use std::sync::Mutex;
+use std::time::Duration;
+
+use tokio::time::Instant;
+
+/// A limiter shared by all workers, hands out one slot every `interval` so the
+/// total queries per second across workers stays near the target.
+pub struct RateLimiter {
+	interval: Duration,
+	next: Mutex<Instant>,
+}
+
+impl RateLimiter {
+	pub fn new(qps: f64) -> Self {
+		RateLimiter {
+			interval: Duration::from_secs_f64(1_f64 / qps),
+			next: Mutex::new(Instant::now()),
+		}
+	}
+
+	/// Wait until the next slot is available.
+	pub async fn acquire(&self) {
+		let at = {
+			let mut next = self.next.lock().unwrap();
+			let at = (*next).max(Instant::now());
+			*next = at + self.interval;
+			at
+		};
+		tokio::time::sleep_until(at).await;
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::time::Duration;
+
+	use tokio::time::Instant;
+
+	use crate::rate::RateLimiter;
+
+	#[tokio::test(start_paused = true)]
+	async fn acquire_should_space_out_slots() {
+		let limiter = RateLimiter::new(10_f64);
+		let start = Instant::now();
+		for _ in 0..5 {
+			limiter.acquire().await;
+		}
+		assert_eq!(start.elapsed(), Duration::from_millis(400));
+	}
+}
@@ -0,0 +1,141 @@
+use anyhow::{anyhow, Context, Result};
+use http::header::{CONTENT_LENGTH, HOST, TRANSFER_ENCODING};
+use http::{HeaderMap, HeaderName, HeaderValue, Method};
+use reqwest::Url;
+
+use crate::har::skip_header;
+use crate::work::Target;
+
+/// Parse a raw HTTP/1.x request, as copied from a capture: the request line,
+/// headers, a blank line and the body. The url is the absolute target of the
+/// request line, or its path on the Host header over `scheme`.
+pub fn parse_raw_request(content: &[u8], scheme: &str) -> Result<Target<Vec<u8>>> {
+	let (head, body) = match blank_line(content) {
+		Some((end, start)) => (&content[..end], &content[start..]),
+		None => (content, &[][..]),
+	};
+	let head = std::str::from_utf8(head).context("request head is not UTF-8")?;
+	let mut lines = head.lines().map(|line| line.trim_end_matches('\r'));
+
+	let request_line = lines.next().unwrap_or_default();
+	let invalid = || anyhow!("invalid request line: {}", request_line);
+	let parts: Vec<&str> = request_line.split(' ').collect();
+	let [method, target, version] = parts[..] else {
+		return Err(invalid());
+	};
+	if !matches!(version, "HTTP/1.0" | "HTTP/1.1") {
+		return Err(invalid());
+	}
+	let method = Method::from_bytes(method.as_bytes()).map_err(|_| invalid())?;
+
+	let mut headers = HeaderMap::new();
+	let mut host = None;
+	let mut content_length = None;
+	for line in lines {
+		let (name, value) = line
+			.split_once(':')
+			.ok_or_else(|| anyhow!("invalid header: {}", line))?;
+		let name = HeaderName::try_from(name.trim())
+			.with_context(|| format!("invalid header name: {}", name))?;
+		let value = HeaderValue::try_from(value.trim())
+			.with_context(|| format!("invalid value of header {}", name))?;
+		if name == HOST {
+			host = Some(value.to_str()?.to_string());
+		} else if name == CONTENT_LENGTH {
+			content_length = Some(value.to_str()?.parse::<usize>()?);
+		} else if name == TRANSFER_ENCODING {
+			return Err(anyhow!("chunked request bodies aren't supported"));
+		} else if !skip_header(name.as_str()) {
+			headers.append(name, value);
+		}
+	}
+
+	let url = if target.starts_with("http://") || target.starts_with("https://") {
+		target.parse::<Url>()
+	} else {
+		let host = host.ok_or_else(|| anyhow!("missing Host header"))?;
+		format!("{}://{}{}", scheme, host, target).parse::<Url>()
+	}
+	.with_context(|| format!("invalid request target: {}", target))?;
+
+	// the trailing newline of the file isn't part of the body
+	let body = match content_length {
+		Some(len) if len > body.len() => {
+			return Err(anyhow!("body is shorter than its Content-Length {}", len))
+		}
+		Some(len) => body[..len].to_vec(),
+		None => body.to_vec(),
+	};
+	Ok(Target {
+		method,
+		url,
+		headers,
+		body,
+	})
+}
+
+/// Where the line ending the head ends and the body starts. Captures pasted
+/// into a file often lose their carriage returns
+fn blank_line(content: &[u8]) -> Option<(usize, usize)> {
+	content.iter().enumerate().find_map(|(i, byte)| {
+		let rest = &content[i + 1..];
+		match byte {
+			b'\n' if rest.starts_with(b"\r\n") => Some((i, i + 3)),
+			b'\n' if rest.starts_with(b"\n") => Some((i, i + 2)),
+			_ => None,
+		}
+	})
+}
+
+#[cfg(test)]
+mod tests {
+	use http::Method;
+
+	use crate::raw::parse_raw_request;
+
+	#[test]
+	fn parse_raw_request_should_work() {
+		let target = parse_raw_request(
+			b"POST /api?a=1 HTTP/1.1\r\nHost: localhost:8080\r\nContent-Type: application/json\r\nContent-Length: 7\r\nConnection: keep-alive\r\n\r\n{\"a\":1}\n",
+			"http",
+		)
+		.unwrap();
+		assert_eq!(target.method, Method::POST);
+		assert_eq!(target.url.as_str(), "http://localhost:8080/api?a=1");
+		assert_eq!(target.headers.len(), 1);
+		assert_eq!(target.headers["content-type"], "application/json");
+		assert_eq!(target.body, b"{\"a\":1}");
+
+		let target = parse_raw_request(b"GET / HTTP/1.1\nHost: example.com\n", "https").unwrap();
+		assert_eq!(target.url.as_str(), "https://example.com/");
+		assert!(target.body.is_empty());
+
+		let target = parse_raw_request(b"GET http://a.com/b HTTP/1.1\n\n", "https").unwrap();
+		assert_eq!(target.url.as_str(), "http://a.com/b");
+	}
+
+	#[test]
+	fn parse_raw_request_should_validate() {
+		for (raw, err) in [
+			(
+				&b"GET /\r\nHost: a\r\n\r\n"[..],
+				"invalid request line: GET /",
+			),
+			(
+				b"GET / HTTP/2\r\nHost: a\r\n\r\n",
+				"invalid request line: GET / HTTP/2",
+			),
+			(b"GET / HTTP/1.1\r\n\r\n", "missing Host header"),
+			(
+				b"GET / HTTP/1.1\r\nHost a\r\n\r\n",
+				"invalid header: Host a",
+			),
+			(
+				b"POST / HTTP/1.1\r\nHost: a\r\nContent-Length: 5\r\n\r\nab",
+				"body is shorter than its Content-Length 5",
+			),
+		] {
+			assert_eq!(parse_raw_request(raw, "http").unwrap_err().to_string(), err);
+		}
+	}
+}
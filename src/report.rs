@@ -1,38 +1,193 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use std::io::Write;
 use std::time::Duration;
 
+use clap::ValueEnum;
+use hdrhistogram::Histogram;
 use serde::{Deserialize, Serialize};
 use tera::{to_value, try_get_value, Context, Filter, Tera, Value};
 
+use crate::compress::BodyCompression;
+
 const BAR_CHAR: &str = "■";
 
+pub const DEFAULT_PERCENTILES: &str = "10,25,50,75,90,95,99";
+
 const TEMPLATE: &str = r#"
-Summary:
+Summary:{% if s.max_errors_reached %}
+  Stopped early:  reached {{ s.max_errors_reached }} errors{% endif %}{% if s.http_version %}
+  HTTP version:  {{ s.http_version }}{% endif %}{% if s.unix_socket %}
+  Unix socket:  {{ s.unix_socket }}, latencies include the hop of the loopback relay{% endif %}{% if s.address_family %}
+  Address family:  {{ s.address_family }}{% endif %}{% if s.ramp_up %}
+  Ramp-up:  {{ s.ramp_up | duration_to_sec_f64 | round(precision=4) }} secs{% endif %}{% if s.max_connections %}
+  Concurrency:  {{ s.workers }} workers over at most {{ s.max_connections }} connections{% endif %}{% if s.open_loop %}
+  Open loop:  latencies count from the scheduled send time{% endif %}{% if s.correct_co %}
+  Coordinated omission:  corrected, latencies count from the scheduled send time{% endif %}
   Total:  {{ s.total | duration_to_sec_f64 | round(precision=4) }} secs
   Slowest:  {{ s.slowest | round(precision=4) }} secs
   Fastest:  {{ s.fastest | round(precision=4) }} secs
   Average:  {{ s.average | round(precision=4) }} secs
-  Requests/sec:  {{ s.rps | round(precision=4) }}
+  Std deviation:  {{ s.stddev | round(precision=4) }} secs
+  Median:  {{ s.p50 | round(precision=4) }} secs
+  Interquartile range:  {{ s.iqr | round(precision=4) }} secs
+  Requests/sec:  {{ s.rps | round(precision=4) }}{% if s.total_requests > 0 %}
+  Success rate:  {{ s.success_rate | round(precision=2) }}%
+  Error rate:  {{ s.error_rate | round(precision=2) }}%{% endif %}{% if s.throttled_requests > 0 %}
+  Throttled responses:  {{ s.throttled_requests }}{% endif %}{% if s.not_modified_requests > 0 %}
+  304 Not Modified:  {{ s.not_modified_requests }}{% endif %}{% if s.user_agents > 0 %}
+  User agents:  {{ s.user_agents }} distinct{% endif %}{% if s.retried_requests > 0 %}
+  Retried requests:  {{ s.retried_requests }}{% endif %}{% if s.handshake_requests > 0 %}
+  Digest handshakes excluded:  {{ s.handshake_requests }}{% endif %}{% if s.redirected_requests > 0 %}
+  Redirected responses:  {{ s.redirected_requests }}, {{ s.redirect_extra_latency | round(precision=4) }} secs slower on average{% endif %}{% if s.slow_threshold %}
+  Slow responses:  {{ s.slow_requests }} over {{ s.slow_threshold | duration_to_sec_f64 | round(precision=4) }} secs{% endif %}{% if s.warmup_requests > 0 %}
+  Warmup requests excluded:  {{ s.warmup_requests }}{% endif %}{% if s.in_flight_requests > 0 %}
+  In-flight at cutoff:  {{ s.in_flight_requests }}{% endif %}{% if s.connection_reuse is number %}
+  Connection reuse (approx.):  {{ s.connection_reuse | round(precision=1) }}%{% endif %}{% if s.requests_per_conn %}
+  Connection lifetime:  {{ s.connection_lifetime | round(precision=4) }} secs on average, closed every {{ s.requests_per_conn }} requests{% endif %}
   {% if s.size_total > 0 %}
-  Total data:	{{ s.size_total | human_bytes }} bytes
-  Size/request:	{{ s.size_req | human_bytes }} bytes {% endif %}
-
+  Total data{% if s.wire_size %} (on the wire){% else %} (decompressed){% endif %}:	{{ s.size_total | human_bytes }} bytes
+  Size/request:	{{ s.size_req | human_bytes }} bytes
+  Transfer rate:	{{ s.throughput_bps | human_bytes }}/s {% endif %}{% if s.body_compression %}
+  Request body:	{{ s.body_compression.original | human_bytes }} bytes, {{ s.body_compression.encoding }} compressed to {{ s.body_compression.compressed | human_bytes }} bytes ({{ s.body_compression.compressed / s.body_compression.original * 100 | round(precision=1) }}%){% endif %}
+{% if s.runs %}
+Runs, the rest of the report is about the last one: {% for run in s.runs.runs %}
+  [{{ loop.index }}]	{{ run.rps | round(precision=4) }} requests/sec, p99 {{ run.p99 | round(precision=4) }} secs{% endfor %}
+  Average:	{{ s.runs.rps_mean | round(precision=4) }} requests/sec, p99 {{ s.runs.p99_mean | round(precision=4) }} secs
+  Requests/sec coefficient of variation:	{{ s.runs.rps_cv | round(precision=2) }}%
+{% endif %}{% if not s.quiet %}
 Response time histogram:
-{{ s.histogram | histogram }}
+{{ s.histogram | histogram(p90=s.p90, p99=s.p99) }}
 Latency distribution: {% for dist in s.latency_dist %}
   {{ dist.percentage }}% in {{ dist.latency | round(precision=4) }} secs {% endfor %}
-
+{% if s.timeline | length > 0 %}
+Throughput over time:
+  {{ s.timeline | sparkline }}{% for count in s.timeline %}
+  [{{ loop.index0 }}s]	{{ count }} requests{% endfor %}
+{% endif %}{% if s.num_res > 0 %}
+Detailed latency breakdown (average):
+  DNS lookup:  {{ s.dns_average | round(precision=4) }} secs
+  Time to first byte, connect and TLS included:  {{ s.ttfb_average | round(precision=4) }} secs
+  Response read:  {{ s.read_average | round(precision=4) }} secs
+{% endif %}
 Status code distribution: {% for code, count in s.status_code_dist %}
   [{{ code }}]	{{ count }} responses{% endfor %}
-{% if s.error_dist | length > 0 %}
+{% if s.status_latency | length > 0 %}
+Latency per status code: {% for code, latency in s.status_latency %}
+  [{{ code }}]	{{ latency.count }} responses, average {{ latency.average | round(precision=4) }} secs, p99 {{ latency.p99 | round(precision=4) }} secs{% if s.verbose %}, fastest {{ latency.fastest | round(precision=4) }} secs, slowest {{ latency.slowest | round(precision=4) }} secs{% endif %}{% endfor %}
+{% endif %}{% if s.url_requests | length > 0 %}
+Status code distribution per URL: {% for url, count in s.url_requests %}
+  {{ url }}	{{ count }} requests ({{ count / s.total_requests * 100 | round(precision=1) }}%){% if s.url_status_dist[url] %}{% for code, count in s.url_status_dist[url] %}
+    [{{ code }}]	{{ count }} responses{% endfor %}{% endif %}{% endfor %}
+{% endif %}{% if s.body_dist | length > 0 %}
+Body files sent: {% for name, count in s.body_dist %}
+  [{{ count }}] {{ name }}{% endfor %}
+{% endif %}{% if s.worker_stats | length > 0 %}
+Per worker: {% for worker, stat in s.worker_stats %}
+  [{{ worker }}]	{{ stat.requests }} requests, {{ stat.errors }} errors, {% if stat.throttled > 0 %}{{ stat.throttled }} throttled, {% endif %}average {{ stat.average | round(precision=4) }} secs{% endfor %}
+{% endif %}{% if s.error_dist | length > 0 %}
 Error distribution: {% for err, count in s.error_dist %}
   [{{ count }}] {{ err }}{% endfor %}{% endif %}
-"#;
+{% endif %}"#;
+
+const MARKDOWN_TEMPLATE: &str = r#"## Summary
+{% if s.max_errors_reached %}
+- Stopped early: reached {{ s.max_errors_reached }} errors{% endif %}{% if s.http_version %}
+- HTTP version: {{ s.http_version }}{% endif %}{% if s.unix_socket %}
+- Unix socket: {{ s.unix_socket }}, latencies include the hop of the loopback relay{% endif %}{% if s.address_family %}
+- Address family: {{ s.address_family }}{% endif %}{% if s.ramp_up %}
+- Ramp-up: {{ s.ramp_up | duration_to_sec_f64 | round(precision=4) }} secs{% endif %}{% if s.max_connections %}
+- Concurrency: {{ s.workers }} workers over at most {{ s.max_connections }} connections{% endif %}{% if s.open_loop %}
+- Open loop: latencies count from the scheduled send time{% endif %}{% if s.correct_co %}
+- Coordinated omission: corrected, latencies count from the scheduled send time{% endif %}
+- Total: {{ s.total | duration_to_sec_f64 | round(precision=4) }} secs
+- Slowest: {{ s.slowest | round(precision=4) }} secs
+- Fastest: {{ s.fastest | round(precision=4) }} secs
+- Average: {{ s.average | round(precision=4) }} secs
+- Std deviation: {{ s.stddev | round(precision=4) }} secs
+- Median: {{ s.p50 | round(precision=4) }} secs
+- Interquartile range: {{ s.iqr | round(precision=4) }} secs
+- Requests/sec: {{ s.rps | round(precision=4) }}{% if s.total_requests > 0 %}
+- Success rate: {{ s.success_rate | round(precision=2) }}%
+- Error rate: {{ s.error_rate | round(precision=2) }}%{% endif %}{% if s.throttled_requests > 0 %}
+- Throttled responses: {{ s.throttled_requests }}{% endif %}{% if s.not_modified_requests > 0 %}
+- 304 Not Modified: {{ s.not_modified_requests }}{% endif %}{% if s.user_agents > 0 %}
+- User agents: {{ s.user_agents }} distinct{% endif %}{% if s.retried_requests > 0 %}
+- Retried requests: {{ s.retried_requests }}{% endif %}{% if s.handshake_requests > 0 %}
+- Digest handshakes excluded: {{ s.handshake_requests }}{% endif %}{% if s.redirected_requests > 0 %}
+- Redirected responses: {{ s.redirected_requests }}, {{ s.redirect_extra_latency | round(precision=4) }} secs slower on average{% endif %}{% if s.slow_threshold %}
+- Slow responses: {{ s.slow_requests }} over {{ s.slow_threshold | duration_to_sec_f64 | round(precision=4) }} secs{% endif %}{% if s.warmup_requests > 0 %}
+- Warmup requests excluded: {{ s.warmup_requests }}{% endif %}{% if s.in_flight_requests > 0 %}
+- In-flight at cutoff: {{ s.in_flight_requests }}{% endif %}{% if s.connection_reuse is number %}
+- Connection reuse (approx.): {{ s.connection_reuse | round(precision=1) }}%{% endif %}{% if s.requests_per_conn %}
+- Connection lifetime: {{ s.connection_lifetime | round(precision=4) }} secs on average, closed every {{ s.requests_per_conn }} requests{% endif %}{% if s.size_total > 0 %}
+- Total data{% if s.wire_size %} (on the wire){% else %} (decompressed){% endif %}: {{ s.size_total | human_bytes }} bytes
+- Size/request: {{ s.size_req | human_bytes }} bytes
+- Transfer rate: {{ s.throughput_bps | human_bytes }}/s{% endif %}{% if s.body_compression %}
+- Request body: {{ s.body_compression.original | human_bytes }} bytes, {{ s.body_compression.encoding }} compressed to {{ s.body_compression.compressed | human_bytes }} bytes ({{ s.body_compression.compressed / s.body_compression.original * 100 | round(precision=1) }}%){% endif %}
+{% if s.runs %}
+## Runs
+
+The rest of the report is about the last run.
+
+| Run | Requests/sec | p99 |
+| ---: | ---: | ---: |{% for run in s.runs.runs %}
+| {{ loop.index }} | {{ run.rps | round(precision=4) }} | {{ run.p99 | round(precision=4) }} secs |{% endfor %}
+| Average | {{ s.runs.rps_mean | round(precision=4) }} | {{ s.runs.p99_mean | round(precision=4) }} secs |
+
+Requests/sec coefficient of variation: {{ s.runs.rps_cv | round(precision=2) }}%
+{% endif %}{% if not s.quiet %}{% if s.latency_dist | length > 0 %}
+## Latency distribution
+
+| Percentile | Latency |
+| ---: | ---: |{% for dist in s.latency_dist %}
+| {{ dist.percentage }}% | {{ dist.latency | round(precision=4) }} secs |{% endfor %}
+{% endif %}{% if s.timeline | length > 0 %}
+## Throughput over time
+
+| Second | Requests |
+| ---: | ---: |{% for count in s.timeline %}
+| {{ loop.index0 }} | {{ count }} |{% endfor %}
+{% endif %}{% if s.status_code_dist | length > 0 %}
+## Status code distribution
+
+| Status code | Responses | Average | p99 |{% if s.verbose %} Fastest | Slowest |{% endif %}
+| ---: | ---: | ---: | ---: |{% if s.verbose %} ---: | ---: |{% endif %}{% for code, count in s.status_code_dist %}
+| {{ code }} | {{ count }} | {% if s.status_latency[code] %}{{ s.status_latency[code].average | round(precision=4) }} secs | {{ s.status_latency[code].p99 | round(precision=4) }} secs{% if s.verbose %} | {{ s.status_latency[code].fastest | round(precision=4) }} secs | {{ s.status_latency[code].slowest | round(precision=4) }} secs{% endif %}{% else %} | {% if s.verbose %} | | {% endif %}{% endif %} |{% endfor %}
+{% endif %}{% if s.body_dist | length > 0 %}
+## Body files sent
+
+| File | Sent |
+| --- | ---: |{% for name, count in s.body_dist %}
+| {{ name | replace(from="|", to="\|") }} | {{ count }} |{% endfor %}
+{% endif %}{% if s.worker_stats | length > 0 %}
+## Per worker
+
+| Worker | Requests | Errors | Throttled | Average |
+| ---: | ---: | ---: | ---: | ---: |{% for worker, stat in s.worker_stats %}
+| {{ worker }} | {{ stat.requests }} | {{ stat.errors }} | {{ stat.throttled }} | {{ stat.average | round(precision=4) }} secs |{% endfor %}
+{% endif %}{% if s.error_dist | length > 0 %}
+## Error distribution
+
+| Error | Count |
+| --- | ---: |{% for err, count in s.error_dist %}
+| {{ err | replace(from="|", to="\|") }} | {{ count }} |{% endfor %}
+{% endif %}{% endif %}"#;
+
+/// Spacing of the histogram buckets between the fastest and slowest latency
+#[derive(Debug, Default, Clone, Copy, PartialEq, ValueEnum)]
+pub enum HistogramScale {
+	#[default]
+	Linear,
+	/// Buckets grow geometrically, for latencies spanning orders of magnitude
+	Log,
+}
 
 #[derive(Debug, Default, Serialize)]
 pub struct LatencyDistribution {
-	percentage: u8,
-	latency: f64,
+	/// Percentile, like 99 for p99
+	pub percentage: f64,
+	/// Latency in seconds
+	pub latency: f64,
 }
 
 #[derive(Debug, Default, Serialize, Deserialize)]
@@ -55,62 +210,459 @@ struct HumanBytesFilter;
 
 impl Filter for HumanBytesFilter {
 	fn filter(&self, value: &Value, _args: &HashMap<String, Value>) -> tera::Result<Value> {
-		let bytes = try_get_value!("human_bytes", "value", u64, value);
-		Ok(to_value(human_bytes::human_bytes(bytes as f64))?)
+		let bytes = try_get_value!("human_bytes", "value", f64, value);
+		Ok(to_value(human_bytes::human_bytes(bytes))?)
 	}
 }
 
+/// A bar of eighths for every value, scaled to the largest value
+fn sparkline(values: &[u64]) -> String {
+	const BARS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+	let max = values.iter().copied().max().unwrap_or_default().max(1);
+	values
+		.iter()
+		.map(|value| BARS[(value * 7 / max) as usize])
+		.collect()
+}
+
+struct SparklineFilter;
+
+impl Filter for SparklineFilter {
+	fn filter(&self, value: &Value, _args: &HashMap<String, Value>) -> tera::Result<Value> {
+		let values = try_get_value!("sparkline", "value", Vec<u64>, value);
+		Ok(to_value(sparkline(&values))?)
+	}
+}
+
+/// A bar for every bucket, the buckets containing the `marks` percentiles,
+/// like `("p99", latency)`, are starred
+fn render_histogram(buckets: &[Bucket], marks: &[(&str, f64)]) -> String {
+	let max = buckets.iter().map(|bucket| bucket.count).max();
+	let mut string = String::default();
+	for (i, bucket) in buckets.iter().enumerate() {
+		let bar = max
+			.map(|value| (bucket.count * 40 + value / 2) / value)
+			.map(|len| BAR_CHAR.repeat(len as usize))
+			.unwrap_or_default();
+		// a bucket holds the latencies up to its mark, the last one every latency above
+		let lower = i.checked_sub(1).map(|i| buckets[i].mark);
+		let names: Vec<&str> = marks
+			.iter()
+			.filter(|(_, latency)| {
+				lower.is_none_or(|lower| *latency > lower)
+					&& (*latency <= bucket.mark || i == buckets.len() - 1)
+			})
+			.map(|(name, _)| *name)
+			.collect();
+		string.push_str(format!("{:>4.3} [{}]\t|{}", bucket.mark, bucket.count, bar).as_str());
+		if !names.is_empty() {
+			string.push_str(format!(" * {}", names.join(", ")).as_str());
+		}
+		string.push('\n');
+	}
+	string
+}
+
 struct HistogramFilter;
 
 impl Filter for HistogramFilter {
-	fn filter(&self, value: &Value, _args: &HashMap<String, Value>) -> tera::Result<Value> {
+	fn filter(&self, value: &Value, args: &HashMap<String, Value>) -> tera::Result<Value> {
 		let buckets = try_get_value!("histogram", "value", Vec<Bucket>, value);
-		let max = buckets.iter().map(|bucket| bucket.count).max();
-		let mut string = String::default();
-		for ref bucket in buckets {
-			let bar = max
-				.map(|value| (bucket.count * 40 + value / 2) / value)
-				.map(|len| BAR_CHAR.repeat(len as usize))
-				.unwrap_or_default();
-			string
-				.push_str(format!("{:>4.3} [{}]\t|{}\n", bucket.mark, bucket.count, bar).as_str());
+		let mut marks = vec![];
+		for name in ["p90", "p99"] {
+			if let Some(latency) = args.get(name) {
+				marks.push((name, try_get_value!("histogram", name, f64, latency)));
+			}
 		}
-		Ok(to_value(string)?)
+		Ok(to_value(render_histogram(&buckets, &marks))?)
 	}
 }
 
 #[derive(Debug, Default, Serialize)]
 pub struct Report {
+	/// Protocol version forced for the run
+	pub http_version: Option<String>,
+	/// Unix socket the requests were relayed to
+	pub unix_socket: Option<String>,
+	/// IPv4, IPv6 or both, of the connections the responses came on
+	pub address_family: Option<String>,
+	/// Time over which the workers were started
+	pub ramp_up: Option<Duration>,
+	pub workers: u16,
+	/// Requests in flight at once across workers, the connections opened
+	pub max_connections: Option<u64>,
+	/// Whether requests were started on a schedule, their latencies then count
+	/// from the time they were due, which corrects coordinated omission
+	pub open_loop: bool,
+	/// Whether the latencies of the requests delayed by slow ones count from
+	/// the time they were due
+	pub correct_co: bool,
+	pub body_compression: Option<BodyCompression>,
+	/// Whether sizes count compressed bytes on the wire instead of decompressed bytes
+	pub wire_size: bool,
+	/// Show the fastest and slowest latency of every status code
+	pub verbose: bool,
+	/// Only show the summary
+	pub quiet: bool,
 	pub avg_total: f64,
 	pub fastest: f64,
 	pub slowest: f64,
 	pub average: f64,
+	/// Sample variance of the latencies, zero with fewer than two samples
+	pub variance: f64,
+	pub stddev: f64,
+	pub p50: f64,
+	/// p75 - p25
+	pub iqr: f64,
+	pub p90: f64,
+	pub p95: f64,
+	pub p99: f64,
 	pub rps: f64,
 
+	/// Time spent resolving names, zero for requests reusing a connection
+	pub dns_average: f64,
+	/// Time until response headers, includes connect and TLS handshake
+	pub ttfb_average: f64,
+	/// Time from response headers to the end of the body
+	pub read_average: f64,
+
 	pub total_requests: u64,
+	/// Percentage of the requests that succeeded, zero when none was sent
+	pub success_rate: f64,
+	/// Percentage of the requests that failed, zero when none was sent
+	pub error_rate: f64,
+	/// Successful requests that needed more than one attempt
+	pub retried_requests: u64,
+	/// Requests fetching a digest challenge, not measured
+	pub handshake_requests: u64,
+	/// Successful responses that followed redirects
+	pub redirected_requests: u64,
+	/// Average latency of the redirected responses above the others, zero
+	/// unless there are both
+	pub redirect_extra_latency: f64,
+	/// Requests sent during the warmup whose results were discarded
+	pub warmup_requests: u64,
+	/// Successful responses slower than `slow_threshold`, counted in the
+	/// latencies as well
+	pub slow_requests: u64,
+	pub slow_threshold: Option<Duration>,
+	/// Responses with a throttling status code, neither successes nor errors
+	pub throttled_requests: u64,
+	/// Successful 304 responses, to conditional requests
+	pub not_modified_requests: u64,
+	/// Distinct User-Agents sent when they were rotated
+	pub user_agents: u64,
+	/// The error threshold that stopped the run early, if it did
+	pub max_errors_reached: Option<u64>,
+	/// Requests started but not counted when the run was cut off
+	pub in_flight_requests: u64,
+	/// Percentage of the responses on a connection opened for an earlier
+	/// one, none when the connections are unknown. Approximate, a new
+	/// connection on the port of a closed one counts as reused when its setup
+	/// took no longer than the reuse threshold
+	pub connection_reuse: Option<f64>,
+	/// Average seconds from the first to the last response on a connection
+	pub connection_lifetime: f64,
+	pub requests_per_conn: Option<u64>,
 
 	pub total: Duration,
 
-	pub error_dist: HashMap<String, u64>,
-	pub status_code_dist: HashMap<u16, u64>,
+	pub error_dist: BTreeMap<String, u64>,
+	pub status_code_dist: BTreeMap<u16, u64>,
+	/// Latency of the successful responses by status code
+	pub status_latency: BTreeMap<u16, StatusLatency>,
+	/// Only filled when more than one url was requested
+	pub url_status_dist: BTreeMap<String, BTreeMap<u16, u64>>,
+	/// Requests sent to each url, only filled when more than one url was requested
+	pub url_requests: BTreeMap<String, u64>,
+	/// Results of every worker, only filled when counted
+	pub worker_stats: BTreeMap<u16, WorkerStat>,
+	/// Every run when the work was repeated
+	pub runs: Option<Runs>,
+	/// Times every file of the body directory was sent, by name
+	pub body_dist: BTreeMap<String, u64>,
 	pub size_total: u64,
 	pub size_req: u64,
+	/// Bytes received per second
+	pub throughput_bps: f64,
 	pub num_res: u64,
 
 	pub latency_dist: Vec<LatencyDistribution>,
 	pub histogram: Vec<Bucket>,
+	/// Requests completed every second of the run, when counted
+	pub timeline: Vec<u64>,
 }
 
 impl Report {
-	pub fn print(&self) {
+	fn render_template(&self, template: &str) -> String {
 		let mut ctx = Context::new();
 		ctx.insert("s", self);
 		let mut tera = Tera::default();
 		tera.register_filter("duration_to_sec_f64", DurationToSecF64Filter);
 		tera.register_filter("human_bytes", HumanBytesFilter);
 		tera.register_filter("histogram", HistogramFilter);
-		let string = tera.render_str(TEMPLATE, &ctx).unwrap();
-		println!("{}", string);
+		tera.register_filter("sparkline", SparklineFilter);
+		tera.render_str(template, &ctx).unwrap()
+	}
+
+	/// The text report
+	pub fn render(&self) -> String {
+		self.render_template(TEMPLATE)
+	}
+
+	/// The report as a Markdown document, without the histogram
+	pub fn render_markdown(&self) -> String {
+		self.render_template(MARKDOWN_TEMPLATE)
+	}
+
+	pub fn print(&self) {
+		println!("{}", self.render());
+	}
+
+	pub fn print_markdown(&self) {
+		println!("{}", self.render_markdown());
+	}
+
+	/// The report as pretty JSON
+	pub fn render_json(&self) -> String {
+		serde_json::to_string_pretty(self).unwrap()
+	}
+
+	pub fn print_json(&self) {
+		println!("{}", self.render_json());
+	}
+}
+
+/// A single request, kept only when per-request records were asked for.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Record {
+	pub duration: Option<f64>,
+	pub status_code: Option<u16>,
+	pub size: u64,
+	pub error: Option<String>,
+}
+
+/// A completed request, streamed as one JSON line while the run goes on.
+#[derive(Debug, Default, Clone, PartialEq, Serialize)]
+pub struct Event {
+	/// Seconds since the start of the run
+	pub t: f64,
+	/// Latency in seconds, none when no response arrived
+	pub duration: Option<f64>,
+	pub status: Option<u16>,
+	pub size: u64,
+	pub error: Option<String>,
+}
+
+impl Event {
+	pub fn write_jsonl<W: Write>(&self, mut writer: W) -> std::io::Result<()> {
+		serde_json::to_writer(&mut writer, self)?;
+		writeln!(writer)
+	}
+}
+
+fn csv_field(s: &str) -> String {
+	if s.contains([',', '"', '\n', '\r']) {
+		format!("\"{}\"", s.replace('"', "\"\""))
+	} else {
+		s.to_string()
+	}
+}
+
+/// Sample variance, zero with fewer than two values
+fn variance(values: &[f64], mean: f64) -> f64 {
+	if values.len() < 2 {
+		return 0_f64;
+	}
+	let sum: f64 = values.iter().map(|value| (value - mean).powi(2)).sum();
+	sum / (values.len() - 1) as f64
+}
+
+/// `count` per second of `total`, zero when `total` is too short to be meaningful
+fn per_sec(count: f64, total: Duration) -> f64 {
+	if total < Duration::from_micros(1) {
+		0_f64
+	} else {
+		count / total.as_secs_f64()
+	}
+}
+
+/// Index of the `p` percentile in `len` sorted values, `ceil(p / 100 * len) - 1`
+fn nearest_rank(p: f64, len: usize) -> usize {
+	let rank = (p * len as f64 / 100_f64).ceil() as usize;
+	rank.clamp(1, len) - 1
+}
+
+/// Latency of the successful responses with one status code
+#[derive(Debug, Default, Clone, PartialEq, Serialize)]
+pub struct StatusLatency {
+	pub count: u64,
+	pub average: f64,
+	pub p99: f64,
+	pub fastest: f64,
+	pub slowest: f64,
+}
+
+/// Requests per second and p99 of one of the repeated runs
+#[derive(Debug, Default, Clone, PartialEq, Serialize)]
+pub struct RunSummary {
+	pub rps: f64,
+	pub p99: f64,
+}
+
+/// Repeated runs of the same work, to see how stable the results are
+#[derive(Debug, Default, Clone, PartialEq, Serialize)]
+pub struct Runs {
+	pub runs: Vec<RunSummary>,
+	pub rps_mean: f64,
+	pub p99_mean: f64,
+	/// Standard deviation of the requests per second relative to their mean, in percent
+	pub rps_cv: f64,
+}
+
+impl Runs {
+	pub fn new(runs: Vec<RunSummary>) -> Self {
+		let len = runs.len().max(1) as f64;
+		let rps: Vec<f64> = runs.iter().map(|run| run.rps).collect();
+		let rps_mean = rps.iter().sum::<f64>() / len;
+		let p99_mean = runs.iter().map(|run| run.p99).sum::<f64>() / len;
+		let rps_cv = if rps_mean > 0_f64 {
+			variance(&rps, rps_mean).sqrt() / rps_mean * 100_f64
+		} else {
+			0_f64
+		};
+		Runs {
+			runs,
+			rps_mean,
+			p99_mean,
+			rps_cv,
+		}
+	}
+}
+
+/// Results of one worker, a worker far behind the others is stuck
+#[derive(Debug, Default, Clone, PartialEq, Serialize)]
+pub struct WorkerStat {
+	pub requests: u64,
+	pub errors: u64,
+	/// Throttled responses, neither successes nor errors
+	pub throttled: u64,
+	/// Average latency of the successful responses
+	pub average: f64,
+}
+
+/// Latencies aggregated in HdrHistograms of microseconds instead of kept one
+/// by one, so memory doesn't grow with the number of requests. Percentiles are
+/// only accurate to 3 significant digits, under 0.1% off, while the average,
+/// deviation, fastest and slowest stay exact.
+#[derive(Debug, Clone)]
+pub struct StreamingLatencies {
+	histogram: Histogram<u64>,
+	sum: f64,
+	sum_squares: f64,
+	fastest: f64,
+	slowest: f64,
+	/// Latencies of every status code
+	status: BTreeMap<u16, StatusHistogram>,
+}
+
+#[derive(Debug, Clone)]
+struct StatusHistogram {
+	histogram: Histogram<u64>,
+	sum: f64,
+	fastest: f64,
+	slowest: f64,
+}
+
+fn latency_histogram() -> Histogram<u64> {
+	Histogram::new(3).expect("3 significant digits are valid")
+}
+
+impl Default for StreamingLatencies {
+	fn default() -> Self {
+		StreamingLatencies {
+			histogram: latency_histogram(),
+			sum: 0_f64,
+			sum_squares: 0_f64,
+			fastest: f64::MAX,
+			slowest: 0_f64,
+			status: BTreeMap::new(),
+		}
+	}
+}
+
+impl StreamingLatencies {
+	/// Record the `duration` in seconds of a response
+	pub fn record(&mut self, status_code: u16, duration: f64) {
+		let micros = (duration * 1_000_000_f64) as u64;
+		// histograms grow instead, saturating would clamp to their initial range
+		let _ = self.histogram.record(micros);
+		self.sum += duration;
+		self.sum_squares += duration * duration;
+		self.fastest = self.fastest.min(duration);
+		self.slowest = self.slowest.max(duration);
+		let status = self
+			.status
+			.entry(status_code)
+			.or_insert_with(|| StatusHistogram {
+				histogram: latency_histogram(),
+				sum: 0_f64,
+				fastest: f64::MAX,
+				slowest: 0_f64,
+			});
+		let _ = status.histogram.record(micros);
+		status.sum += duration;
+		status.fastest = status.fastest.min(duration);
+		status.slowest = status.slowest.max(duration);
+	}
+
+	fn count(&self) -> u64 {
+		self.histogram.len()
+	}
+
+	/// Sample variance, zero with fewer than two values
+	fn variance(&self) -> f64 {
+		let count = self.count() as f64;
+		if count < 2_f64 {
+			return 0_f64;
+		}
+		((self.sum_squares - self.sum * self.sum / count) / (count - 1_f64)).max(0_f64)
+	}
+
+	fn percentile(&self, p: f64) -> f64 {
+		if self.count() == 0 {
+			return 0_f64;
+		}
+		let micros = self.histogram.value_at_percentile(p);
+		(micros as f64 / 1_000_000_f64).clamp(self.fastest, self.slowest)
+	}
+
+	/// Recorded values in seconds along with their count, in order
+	fn values(&self) -> impl Iterator<Item = (f64, u64)> + '_ {
+		self.histogram.iter_recorded().map(|value| {
+			(
+				value.value_iterated_to() as f64 / 1_000_000_f64,
+				value.count_at_value(),
+			)
+		})
+	}
+
+	fn status_latency(&self) -> BTreeMap<u16, StatusLatency> {
+		self.status
+			.iter()
+			.map(|(status_code, status)| {
+				let count = status.histogram.len();
+				let p99 = status.histogram.value_at_percentile(99_f64) as f64 / 1_000_000_f64;
+				let latency = StatusLatency {
+					count,
+					average: status.sum / count as f64,
+					p99: p99.clamp(status.fastest, status.slowest),
+					fastest: status.fastest,
+					slowest: status.slowest,
+				};
+				(*status_code, latency)
+			})
+			.collect()
 	}
 }
 
@@ -118,106 +670,845 @@ impl Report {
 pub struct Reporter {
 	pub total_requests: u64,
 	pub success_requests: u64,
-	pub status_codes: Vec<u16>,
+	/// Status codes of every response, successful or not
+	pub status_code_dist: BTreeMap<u16, u64>,
 	pub size_total: u64,
+	pub dns_total: f64,
+	pub ttfb_total: f64,
+	pub retried_requests: u64,
+	pub handshake_requests: u64,
+	pub redirected_requests: u64,
+	/// Sum of the durations of the redirected responses in seconds
+	pub redirected_total: f64,
+	/// Successful responses slower than `slow_threshold`
+	pub slow_requests: u64,
+	pub slow_threshold: Option<Duration>,
+	/// Responses with a throttling status code, neither successes nor errors
+	pub throttled_requests: u64,
+	/// Successful 304 responses
+	pub not_modified_requests: u64,
+	/// Distinct User-Agents sent when they were rotated
+	pub user_agents: u64,
+	/// Times every file of the body directory was sent, by name
+	pub body_dist: BTreeMap<String, u64>,
+	/// Connections the responses came on, when their local address is known
+	pub new_connections: u64,
+	/// Responses on a connection an earlier response came on
+	pub reused_connections: u64,
+	/// Average seconds from the first to the last response on a connection
+	pub connection_lifetime: f64,
+	/// Requests sent on a connection before it was closed, when asked for
+	pub requests_per_conn: Option<u64>,
+	/// IPv4, IPv6 or both, of the connections
+	pub address_family: Option<String>,
+	pub warmup_requests: u64,
+	/// Time since the start at which the last warmup result was received
+	pub warmup_elapsed: Duration,
 	pub error_dist: HashMap<String, u64>,
-	pub durations: Vec<f64>,
+	pub url_status_dist: BTreeMap<String, BTreeMap<u16, u64>>,
+	pub url_requests: BTreeMap<String, u64>,
+	/// Results, successful and throttled responses and the sum of the durations
+	/// in seconds of the successful ones of every worker, only counted when asked for
+	pub worker_dist: BTreeMap<u16, (u64, u64, u64, f64)>,
+	/// Status code and duration of every successful response, empty when
+	/// they are streamed instead
+	pub responses: Vec<(u16, f64)>,
+	/// Latencies of the successful responses, instead of `responses`
+	pub streaming: Option<StreamingLatencies>,
+	/// Requests started but not counted when the run was cut off
+	pub in_flight_requests: u64,
+	pub records: Vec<Record>,
+	/// Results received every second since the start, only counted when asked for
+	pub timeline: Vec<u64>,
+	/// The error threshold that stopped the run early, if it did
+	pub max_errors_reached: Option<u64>,
+	pub histogram_scale: HistogramScale,
+	/// Latency of every successful response in microseconds, only recorded
+	/// when asked for
+	pub hdr_histogram: Option<Histogram<u64>>,
 }
 
 impl Reporter {
-	fn histogram(&self, fastest: f64, slowest: f64) -> Vec<Bucket> {
-		if self.success_requests == 0 {
-			return vec![];
-		}
-		let bc = 10_usize;
-		let mut buckets: Vec<f64> = Vec::with_capacity(bc + 1);
-		let mut counts: Vec<u64> = vec![0; bc + 1];
-		let bs = (slowest - fastest) / bc as f64;
-		for i in 0..bc {
-			buckets.push(fastest + bs * (i as f64));
-		}
-		buckets.push(slowest);
-
-		let mut bi = 0_usize;
-		let mut max = 0_u64;
-		let mut i = 0_usize;
-		while i < self.durations.len() {
-			if self.durations[i] <= buckets[bi] {
-				i += 1;
-				counts[bi] += 1;
-				if max < counts[bi] {
-					max = counts[bi]
-				}
-			} else if bi < buckets.len() - 1 {
-				bi += 1;
-			}
+	pub fn write_csv<W: Write>(&self, mut writer: W) -> std::io::Result<()> {
+		writeln!(writer, "duration,status_code,size,error")?;
+		for record in &self.records {
+			writeln!(
+				writer,
+				"{},{},{},{}",
+				record
+					.duration
+					.map(|duration| duration.to_string())
+					.unwrap_or_default(),
+				record
+					.status_code
+					.map(|code| code.to_string())
+					.unwrap_or_default(),
+				record.size,
+				record.error.as_deref().map(csv_field).unwrap_or_default(),
+			)?;
 		}
-
-		buckets
-			.iter()
-			.zip(counts.iter())
-			.map(|(bucket, count)| Bucket {
-				mark: *bucket,
-				count: *count,
-				frequency: (*count) as f64 / self.durations.len() as f64,
-			})
-			.collect()
+		writer.flush()
 	}
 
-	fn latencies(&self) -> Vec<LatencyDistribution> {
-		let pctls = [10_u8, 25, 50, 75, 90, 95, 99];
-		let mut data = vec![];
-		let mut i = 0_usize;
-		let mut j = 0_usize;
-		while i < self.durations.len() && data.len() < pctls.len() {
-			let current = i * 100 / self.durations.len();
-			if current >= pctls[j] as usize {
-				data.push(self.durations[i]);
-				j += 1;
-			}
-			i += 1;
-		}
-		pctls
+	/// Build the report, `percentiles` are those of the latency distribution
+	pub fn into_report(self, total: Duration, percentiles: &[f64]) -> Report {
+		let streaming = self.streaming;
+		let mut durations: Vec<f64> = self
+			.responses
 			.iter()
-			.zip(data.iter())
-			.map(|(p, d)| LatencyDistribution {
-				percentage: *p,
-				latency: *d,
-			})
-			.collect()
-	}
-
-	pub fn into_report(mut self, total: Duration) -> Report {
+			.map(|(_, duration)| *duration)
+			.collect();
 		let mut report = Report {
 			total,
-			rps: self.total_requests as f64 / total.as_secs_f64(),
-			avg_total: self.durations.iter().sum(),
+			rps: per_sec(self.total_requests as f64, total),
+			throughput_bps: per_sec(self.size_total as f64, total),
+			avg_total: match &streaming {
+				Some(latencies) => latencies.sum,
+				None => durations.iter().sum(),
+			},
 			total_requests: self.total_requests,
+			retried_requests: self.retried_requests,
+			handshake_requests: self.handshake_requests,
+			redirected_requests: self.redirected_requests,
+			warmup_requests: self.warmup_requests,
+			slow_requests: self.slow_requests,
+			slow_threshold: self.slow_threshold,
+			throttled_requests: self.throttled_requests,
+			not_modified_requests: self.not_modified_requests,
+			user_agents: self.user_agents,
+			max_errors_reached: self.max_errors_reached,
+			address_family: self.address_family,
+			in_flight_requests: self.in_flight_requests,
 			size_total: self.size_total,
+			num_res: self.success_requests,
+			timeline: self.timeline,
+			connection_lifetime: self.connection_lifetime,
+			requests_per_conn: self.requests_per_conn,
 			..Report::default()
 		};
+		let connections = self.new_connections + self.reused_connections;
+		if connections > 0 {
+			report.connection_reuse =
+				Some(self.reused_connections as f64 / connections as f64 * 100_f64);
+		}
+		if self.total_requests > 0 {
+			report.success_rate =
+				self.success_requests as f64 / self.total_requests as f64 * 100_f64;
+			report.error_rate =
+				(self.total_requests - self.success_requests - self.throttled_requests) as f64
+					/ self.total_requests as f64
+					* 100_f64;
+		}
 		if self.success_requests > 0 {
 			report.average = report.avg_total / self.success_requests as f64;
 			report.size_req = self.size_total / self.success_requests;
+			report.dns_average = self.dns_total / self.success_requests as f64;
+			report.ttfb_average = self.ttfb_total / self.success_requests as f64;
+			report.read_average = report.average - report.ttfb_average;
+			report.variance = match &streaming {
+				Some(latencies) => latencies.variance(),
+				None => variance(&durations, report.average),
+			};
+			let direct_requests = self.success_requests - self.redirected_requests;
+			if self.redirected_requests > 0 && direct_requests > 0 {
+				report.redirect_extra_latency = self.redirected_total
+					/ self.redirected_requests as f64
+					- (report.avg_total - self.redirected_total) / direct_requests as f64;
+			}
+			report.stddev = report.variance.sqrt();
 		} else {
 			report.average = 0.0;
 			report.size_req = 0;
 		}
 
-		self.durations.sort_by(|a, b| a.total_cmp(b));
-		report.fastest = *self.durations.first().unwrap_or(&0.0);
-		report.slowest = *self.durations.last().unwrap_or(&0.0);
-		report.histogram = self.histogram(report.fastest, report.slowest);
-		report.latency_dist = self.latencies();
-		report.error_dist = self.error_dist;
-		report.status_code_dist =
-			self.status_codes
-				.into_iter()
-				.fold(HashMap::new(), |mut map, code| {
-					*map.entry(code).or_insert(0) += 1;
-					map
-				});
+		durations.sort_by(|a, b| a.total_cmp(b));
+		let mut percentiles = percentiles.to_vec();
+		percentiles.sort_by(|a, b| a.total_cmp(b));
+		percentiles.dedup();
+		match &streaming {
+			Some(latencies) => {
+				// without a latency the bounds are left at zero, like those of the exact path
+				if latencies.count() > 0 {
+					report.fastest = latencies.fastest;
+					report.slowest = latencies.slowest;
+				}
+				report.histogram = histogram_of(
+					latencies.values(),
+					latencies.count(),
+					report.fastest,
+					report.slowest,
+					self.histogram_scale,
+				);
+				report.p50 = latencies.percentile(50_f64);
+				report.iqr = latencies.percentile(75_f64) - latencies.percentile(25_f64);
+				report.p90 = latencies.percentile(90_f64);
+				report.p95 = latencies.percentile(95_f64);
+				report.p99 = latencies.percentile(99_f64);
+				if latencies.count() > 0 {
+					report.latency_dist = percentiles
+						.iter()
+						.map(|p| LatencyDistribution {
+							percentage: *p,
+							latency: latencies.percentile(*p),
+						})
+						.collect();
+				}
+				report.status_latency = latencies.status_latency();
+			}
+			None => {
+				report.fastest = *durations.first().unwrap_or(&0.0);
+				report.slowest = *durations.last().unwrap_or(&0.0);
+				report.histogram = histogram(
+					&durations,
+					report.fastest,
+					report.slowest,
+					self.histogram_scale,
+				);
+				report.p50 = percentile(&durations, 50_f64);
+				report.iqr = percentile(&durations, 75_f64) - percentile(&durations, 25_f64);
+				report.p90 = percentile(&durations, 90_f64);
+				report.p95 = percentile(&durations, 95_f64);
+				report.p99 = percentile(&durations, 99_f64);
+				report.latency_dist = latencies(&durations, &percentiles);
+				report.status_latency = status_latency(self.responses);
+			}
+		}
+		report.error_dist = self.error_dist.into_iter().collect();
+		report.url_status_dist = self.url_status_dist;
+		report.url_requests = self.url_requests;
+		report.body_dist = self.body_dist;
+		report.worker_stats = self
+			.worker_dist
+			.into_iter()
+			.map(|(worker, (requests, success, throttled, total))| {
+				let stat = WorkerStat {
+					requests,
+					errors: requests - success - throttled,
+					throttled,
+					average: if success > 0 {
+						total / success as f64
+					} else {
+						0_f64
+					},
+				};
+				(worker, stat)
+			})
+			.collect();
+		report.status_code_dist = self.status_code_dist;
 
 		report
 	}
 }
+
+fn histogram(sorted: &[f64], fastest: f64, slowest: f64, scale: HistogramScale) -> Vec<Bucket> {
+	histogram_of(
+		sorted.iter().map(|duration| (*duration, 1)),
+		sorted.len() as u64,
+		fastest,
+		slowest,
+		scale,
+	)
+}
+
+/// Histogram of sorted `(value, count)` pairs whose counts sum to `len`
+fn histogram_of<I: Iterator<Item = (f64, u64)>>(
+	values: I,
+	len: u64,
+	fastest: f64,
+	slowest: f64,
+	scale: HistogramScale,
+) -> Vec<Bucket> {
+	if len == 0 {
+		return vec![];
+	}
+	let bc = 10_usize;
+	let mut buckets: Vec<f64> = Vec::with_capacity(bc + 1);
+	let mut counts: Vec<u64> = vec![0; bc + 1];
+	match scale {
+		HistogramScale::Linear => {
+			let bs = (slowest - fastest) / bc as f64;
+			for i in 0..bc {
+				buckets.push(fastest + bs * (i as f64));
+			}
+		}
+		HistogramScale::Log => {
+			// a latency of zero has no logarithm
+			let fastest = fastest.max(1e-6);
+			let ratio = (slowest.max(fastest) / fastest).powf(1_f64 / bc as f64);
+			for i in 0..bc {
+				buckets.push(fastest * ratio.powi(i as i32));
+			}
+		}
+	}
+	buckets.push(slowest);
+
+	// values above the slowest, which histogram values can be, go to the last bucket
+	let mut bi = 0_usize;
+	for (value, count) in values {
+		while value > buckets[bi] && bi < buckets.len() - 1 {
+			bi += 1;
+		}
+		counts[bi] += count;
+	}
+
+	buckets
+		.iter()
+		.zip(counts.iter())
+		.map(|(bucket, count)| Bucket {
+			mark: *bucket,
+			count: *count,
+			frequency: (*count) as f64 / len as f64,
+		})
+		.collect()
+}
+
+/// Nearest-rank percentile of sorted values
+fn percentile(sorted: &[f64], p: f64) -> f64 {
+	if sorted.is_empty() {
+		return 0_f64;
+	}
+	sorted[nearest_rank(p, sorted.len())]
+}
+
+/// Nearest-rank percentiles of sorted values
+fn latencies(sorted: &[f64], pctls: &[f64]) -> Vec<LatencyDistribution> {
+	if sorted.is_empty() {
+		return vec![];
+	}
+	pctls
+		.iter()
+		.map(|p| LatencyDistribution {
+			percentage: *p,
+			latency: percentile(sorted, *p),
+		})
+		.collect()
+}
+
+fn status_latency(responses: Vec<(u16, f64)>) -> BTreeMap<u16, StatusLatency> {
+	let mut grouped: BTreeMap<u16, Vec<f64>> = BTreeMap::new();
+	for (status_code, duration) in responses {
+		grouped.entry(status_code).or_default().push(duration);
+	}
+	grouped
+		.into_iter()
+		.map(|(status_code, mut durations)| {
+			durations.sort_by(|a, b| a.total_cmp(b));
+			let latency = StatusLatency {
+				count: durations.len() as u64,
+				average: durations.iter().sum::<f64>() / durations.len() as f64,
+				p99: percentile(&durations, 99_f64),
+				fastest: durations[0],
+				slowest: durations[durations.len() - 1],
+			};
+			(status_code, latency)
+		})
+		.collect()
+}
+
+#[cfg(test)]
+mod tests {
+	use std::time::Duration;
+
+	use crate::compress::{BodyCompression, Compression};
+	use crate::report::{
+		histogram, render_histogram, sparkline, variance, HistogramScale, Record, Report, Reporter,
+		RunSummary, Runs, StatusLatency, StreamingLatencies, WorkerStat, DEFAULT_PERCENTILES,
+	};
+
+	#[test]
+	fn into_report_should_promote_common_percentiles() {
+		let reporter = Reporter {
+			total_requests: 100,
+			success_requests: 100,
+			responses: (1..=100).map(|i| (200, i as f64)).collect(),
+			..Reporter::default()
+		};
+		let report = reporter.into_report(Duration::from_secs(1), &[95_f64]);
+		assert_eq!(
+			(report.p50, report.p90, report.p95, report.p99),
+			(50_f64, 90_f64, 95_f64, 99_f64)
+		);
+		assert_eq!(report.p95, report.latency_dist[0].latency);
+		let json = serde_json::to_value(&report).unwrap();
+		assert_eq!(json["p95"], 95_f64);
+		assert_eq!(json["latency_dist"][0]["percentage"], 95_f64);
+	}
+
+	#[test]
+	fn into_report_should_compute_median_and_iqr() {
+		let reporter = Reporter {
+			total_requests: 10,
+			success_requests: 10,
+			responses: (1..=10).rev().map(|i| (200, i as f64)).collect(),
+			..Reporter::default()
+		};
+		let report = reporter.into_report(Duration::from_secs(1), &[25_f64, 50.0, 75.0]);
+		assert_eq!(report.p50, 5_f64);
+		assert_eq!(report.iqr, 8_f64 - 3_f64);
+		assert_eq!(report.p50, report.latency_dist[1].latency);
+	}
+
+	#[test]
+	fn variance_should_work() {
+		let values = [2_f64, 4.0, 4.0, 4.0, 5.0, 5.0, 7.0, 9.0];
+		let variance = variance(&values, 5_f64);
+		assert!((variance - 32_f64 / 7_f64).abs() < 1e-12);
+		assert!((variance.sqrt() - 2.138089935299395).abs() < 1e-12);
+		assert_eq!(super::variance(&[1_f64], 1_f64), 0_f64);
+		assert_eq!(super::variance(&[], 0_f64), 0_f64);
+	}
+
+	fn latencies(durations: Vec<f64>, pctls: &[f64]) -> Vec<f64> {
+		super::latencies(&durations, pctls)
+			.iter()
+			.map(|dist| dist.latency)
+			.collect()
+	}
+
+	#[test]
+	fn into_report_should_group_latency_by_status_code() {
+		let mut responses: Vec<(u16, f64)> = (1..=100).map(|i| (200, i as f64)).collect();
+		responses.extend([(500, 200_f64), (500, 400_f64)]);
+		let reporter = Reporter {
+			total_requests: 102,
+			success_requests: 102,
+			responses,
+			..Reporter::default()
+		};
+		let report = reporter.into_report(Duration::from_secs(1), &[50_f64]);
+		assert_eq!(
+			report.status_latency[&200],
+			StatusLatency {
+				count: 100,
+				average: 50.5,
+				p99: 99_f64,
+				fastest: 1_f64,
+				slowest: 100_f64,
+			}
+		);
+		assert_eq!(
+			report.status_latency[&500],
+			StatusLatency {
+				count: 2,
+				average: 300_f64,
+				p99: 400_f64,
+				fastest: 200_f64,
+				slowest: 400_f64,
+			}
+		);
+		assert_eq!(report.slowest, 400_f64);
+	}
+
+	#[test]
+	fn latencies_should_use_nearest_rank() {
+		let pctls = [10_f64, 50.0, 90.0, 99.0, 99.9];
+		assert_eq!(latencies(vec![], &pctls), Vec::<f64>::new());
+		assert_eq!(latencies(vec![1.0], &pctls), vec![1.0; 5]);
+		assert_eq!(
+			latencies(vec![1.0, 2.0], &pctls),
+			vec![1.0, 1.0, 2.0, 2.0, 2.0]
+		);
+		let ten = (1..=10).map(|i| i as f64).collect();
+		assert_eq!(latencies(ten, &pctls), vec![1.0, 5.0, 9.0, 10.0, 10.0]);
+		let thousand = (1..=1000).map(|i| i as f64).collect();
+		assert_eq!(
+			latencies(thousand, &pctls),
+			vec![100.0, 500.0, 900.0, 990.0, 999.0]
+		);
+	}
+
+	#[test]
+	fn write_csv_should_work() {
+		let reporter = Reporter {
+			records: vec![
+				Record {
+					duration: Some(0.5),
+					status_code: Some(200),
+					size: 5,
+					error: None,
+				},
+				Record {
+					error: Some("error sending request, \"refused\"".to_string()),
+					..Record::default()
+				},
+			],
+			..Reporter::default()
+		};
+		let mut buf = vec![];
+		reporter.write_csv(&mut buf).unwrap();
+		assert_eq!(
+			String::from_utf8(buf).unwrap(),
+			"duration,status_code,size,error\n\
+			 0.5,200,5,\n\
+			 ,,0,\"error sending request, \"\"refused\"\"\"\n"
+		);
+	}
+
+	#[test]
+	fn into_report_should_be_finite_on_instant_finish() {
+		let percentiles: Vec<f64> = DEFAULT_PERCENTILES
+			.split(',')
+			.map(|p| p.parse().unwrap())
+			.collect();
+		let report = Reporter::default().into_report(Duration::ZERO, &percentiles);
+		for value in [
+			report.rps,
+			report.throughput_bps,
+			report.average,
+			report.fastest,
+			report.slowest,
+			report.dns_average,
+			report.ttfb_average,
+			report.read_average,
+			report.variance,
+			report.stddev,
+			report.p50,
+			report.iqr,
+			report.success_rate,
+			report.error_rate,
+		] {
+			assert_eq!(value, 0_f64);
+		}
+		assert!(report.histogram.is_empty());
+		assert!(report.latency_dist.is_empty());
+
+		let reporter = Reporter {
+			total_requests: 3,
+			..Reporter::default()
+		};
+		assert_eq!(
+			reporter.into_report(Duration::ZERO, &percentiles).rps,
+			0_f64
+		);
+	}
+
+	#[test]
+	fn render_should_show_summary_and_distributions() {
+		let reporter = Reporter {
+			total_requests: 3,
+			success_requests: 2,
+			status_code_dist: [(200, 2)].into_iter().collect(),
+			error_dist: [("timeout: operation timed out".to_string(), 1)]
+				.into_iter()
+				.collect(),
+			responses: vec![(200, 0.1), (200, 0.3)],
+			size_total: 2048,
+			..Reporter::default()
+		};
+		let text = reporter
+			.into_report(Duration::from_secs(2), &[50_f64, 99.0])
+			.render();
+		let expected = [
+			"Summary:\n  Total:  2 secs\n  Slowest:  0.3 secs\n  Fastest:  0.1 secs\n  Average:  0.2 secs\n",
+			"  Requests/sec:  1.5\n  Success rate:  66.67%\n  Error rate:  33.33%\n",
+			"  Total data (decompressed):\t2 KiB bytes\n  Size/request:\t1 KiB bytes\n  Transfer rate:\t1 KiB/s",
+			"Latency distribution: \n  50% in 0.1 secs \n  99% in 0.3 secs",
+			"Status code distribution: \n  [200]\t2 responses\n",
+			"Latency per status code: \n  [200]\t2 responses, average 0.2 secs, p99 0.3 secs\n",
+			"Error distribution: \n  [1] timeout: operation timed out\n",
+		];
+		for part in expected {
+			assert!(text.contains(part), "{:?} not in {}", part, text);
+		}
+		assert!(!text.contains("Status code distribution per URL"));
+	}
+
+	#[test]
+	fn runs_should_compute_coefficient_of_variation() {
+		let runs = Runs::new(vec![
+			RunSummary {
+				rps: 90_f64,
+				p99: 0.1,
+			},
+			RunSummary {
+				rps: 100_f64,
+				p99: 0.2,
+			},
+			RunSummary {
+				rps: 110_f64,
+				p99: 0.3,
+			},
+		]);
+		assert_eq!(runs.rps_mean, 100_f64);
+		assert!((runs.p99_mean - 0.2).abs() < 1e-9);
+		assert_eq!(runs.rps_cv, 10_f64);
+		assert_eq!(Runs::new(vec![]).rps_cv, 0_f64);
+
+		let report = Report {
+			runs: Some(runs),
+			..Report::default()
+		};
+		let text = report.render();
+		assert!(text.contains("  [2]\t100 requests/sec, p99 0.2 secs\n"));
+		assert!(text.contains("Requests/sec coefficient of variation:\t10%"));
+	}
+
+	#[test]
+	fn render_should_only_show_summary_when_quiet() {
+		let reporter = Reporter {
+			total_requests: 2,
+			success_requests: 1,
+			status_code_dist: [(200, 1)].into_iter().collect(),
+			error_dist: [("timeout".to_string(), 1)].into_iter().collect(),
+			responses: vec![(200, 0.1)],
+			..Reporter::default()
+		};
+		let mut report = reporter.into_report(Duration::from_secs(1), &[50_f64]);
+		report.quiet = true;
+		for text in [report.render(), report.render_markdown()] {
+			assert!(text.contains("Requests/sec"));
+			assert!(text.contains("Error rate"));
+			assert!(!text.contains("histogram"));
+			assert!(!text.contains("distribution"));
+		}
+	}
+
+	#[test]
+	fn render_should_state_the_unix_relay() {
+		let mut report = Reporter::default().into_report(Duration::from_secs(1), &[50_f64]);
+		report.unix_socket = Some("/run/app.sock".to_string());
+		for text in [report.render(), report.render_markdown()] {
+			assert!(
+				text.contains("/run/app.sock, latencies include the hop of the loopback relay"),
+				"{}",
+				text
+			);
+		}
+	}
+
+	#[test]
+	fn render_should_show_body_compression() {
+		let mut report = Reporter::default().into_report(Duration::from_secs(1), &[50_f64]);
+		report.body_compression = Some(BodyCompression {
+			encoding: Compression::Gzip,
+			original: 1000,
+			compressed: 250,
+		});
+		for text in [report.render(), report.render_markdown()] {
+			assert!(
+				text.contains("gzip compressed to 250 B bytes (25%)"),
+				"{}",
+				text
+			);
+		}
+	}
+
+	#[test]
+	fn render_should_show_fastest_and_slowest_per_status_when_verbose() {
+		let reporter = Reporter {
+			total_requests: 3,
+			success_requests: 3,
+			status_code_dist: [(200, 2), (500, 1)].into_iter().collect(),
+			responses: vec![(200, 0.1), (500, 0.2), (200, 0.3)],
+			..Reporter::default()
+		};
+		let mut report = reporter.into_report(Duration::from_secs(1), &[50_f64]);
+		assert!(!report.render().contains("fastest"));
+		report.verbose = true;
+		let text = report.render();
+		assert!(text.contains(
+			"[200]\t2 responses, average 0.2 secs, p99 0.3 secs, fastest 0.1 secs, slowest 0.3 secs\n"
+		));
+		assert!(text.contains("[500]\t1 responses, average 0.2 secs, p99 0.2 secs, fastest 0.2 secs, slowest 0.2 secs"));
+	}
+
+	#[test]
+	fn render_markdown_should_use_tables() {
+		let reporter = Reporter {
+			total_requests: 3,
+			success_requests: 2,
+			status_code_dist: [(200, 2), (503, 1)].into_iter().collect(),
+			error_dist: [("unexpected status 503".to_string(), 1)]
+				.into_iter()
+				.collect(),
+			responses: vec![(200, 0.1), (200, 0.3)],
+			..Reporter::default()
+		};
+		let report = reporter.into_report(Duration::from_secs(2), &[50_f64, 99.0]);
+		let text = report.render_markdown();
+		let expected = [
+			"## Summary\n\n- Total: 2 secs\n",
+			"- Requests/sec: 1.5\n- Success rate: 66.67%\n- Error rate: 33.33%\n",
+			"| Percentile | Latency |\n| ---: | ---: |\n| 50% | 0.1 secs |\n| 99% | 0.3 secs |\n",
+			"| 200 | 2 | 0.2 secs | 0.3 secs |\n| 503 | 1 |  |  |\n",
+			"| unexpected status 503 | 1 |\n",
+		];
+		for part in expected {
+			assert!(text.contains(part), "{:?} not in {}", part, text);
+		}
+		assert!(!text.contains("■"));
+		assert!(!text.contains("Total data"));
+	}
+
+	#[test]
+	fn render_should_show_early_stop() {
+		let reporter = Reporter {
+			total_requests: 5,
+			max_errors_reached: Some(5),
+			in_flight_requests: 2,
+			..Reporter::default()
+		};
+		let report = reporter.into_report(Duration::from_secs(1), &[]);
+		assert!(report
+			.render()
+			.starts_with("\nSummary:\n  Stopped early:  reached 5 errors\n"));
+		assert!(report.render().contains("  In-flight at cutoff:  2\n"));
+		assert!(report
+			.render_markdown()
+			.contains("- Stopped early: reached 5 errors\n"));
+	}
+
+	#[test]
+	fn render_should_show_timeline() {
+		assert_eq!(sparkline(&[0, 4, 8, 2]), "▁▄█▂");
+		assert_eq!(sparkline(&[0, 0]), "▁▁");
+		let reporter = Reporter {
+			total_requests: 12,
+			timeline: vec![2, 10],
+			..Reporter::default()
+		};
+		let report = reporter.into_report(Duration::from_secs(2), &[]);
+		assert!(report
+			.render()
+			.contains("Throughput over time:\n  ▂█\n  [0s]\t2 requests\n  [1s]\t10 requests\n"));
+		assert!(report.render_markdown().contains("| 1 | 10 |"));
+		let report = Reporter::default().into_report(Duration::from_secs(1), &[]);
+		assert!(!report.render().contains("Throughput over time"));
+	}
+
+	#[test]
+	fn into_report_should_compare_redirected_latency() {
+		let reporter = Reporter {
+			total_requests: 4,
+			success_requests: 4,
+			responses: vec![(200, 1_f64), (200, 1_f64), (200, 3_f64), (200, 5_f64)],
+			redirected_requests: 2,
+			redirected_total: 8_f64,
+			..Reporter::default()
+		};
+		let report = reporter.into_report(Duration::from_secs(10), &[]);
+		assert_eq!(report.redirected_requests, 2);
+		assert_eq!(report.redirect_extra_latency, 3_f64);
+		assert!(report
+			.render()
+			.contains("  Redirected responses:  2, 3 secs slower on average\n"));
+	}
+
+	#[test]
+	fn render_histogram_should_star_percentile_buckets() {
+		let sorted: Vec<f64> = (1..=100).map(|i| i as f64 / 100_f64).collect();
+		let buckets = histogram(&sorted, 0.01, 1_f64, HistogramScale::Linear);
+		let text = render_histogram(&buckets, &[("p90", 0.9), ("p99", 0.99)]);
+		let lines: Vec<&str> = text.lines().collect();
+		assert!(lines[9].starts_with("0.901 [10]") && lines[9].ends_with(" * p90"));
+		assert!(lines[10].ends_with(" * p99"));
+		assert_eq!(text.matches('*').count(), 2);
+		let text = render_histogram(&buckets, &[("p90", 0.995), ("p99", 2_f64)]);
+		assert!(text.lines().last().unwrap().ends_with(" * p90, p99"));
+	}
+
+	#[test]
+	fn histogram_should_space_log_buckets_geometrically() {
+		let sorted = [0_f64, 0.001, 0.01, 0.1, 1_f64];
+		let buckets = histogram(&sorted, 0_f64, 1_f64, HistogramScale::Log);
+		let marks: Vec<f64> = buckets.iter().map(|bucket| bucket.mark).collect();
+		assert_eq!(marks.len(), 11);
+		assert_eq!(marks[0], 1e-6);
+		for i in 1..10 {
+			assert!((marks[i] / marks[i - 1] - 10_f64.powf(0.6)).abs() < 1e-9);
+		}
+		assert_eq!(marks[10], 1_f64);
+		assert_eq!(buckets.iter().map(|bucket| bucket.count).sum::<u64>(), 5);
+		assert_eq!(buckets[0].count, 1);
+
+		let buckets = histogram(&[0.5, 0.5], 0.5, 0.5, HistogramScale::Log);
+		assert!(buckets.iter().all(|bucket| bucket.mark == 0.5));
+		assert_eq!(buckets[0].count, 2);
+	}
+
+	#[test]
+	fn into_report_should_not_count_throttled_as_worker_errors() {
+		let reporter = Reporter {
+			worker_dist: [(0, (4, 1, 2, 0.5))].into_iter().collect(),
+			..Reporter::default()
+		};
+		let report = reporter.into_report(Duration::from_secs(1), &[50_f64]);
+		assert_eq!(
+			report.worker_stats[&0],
+			WorkerStat {
+				requests: 4,
+				errors: 1,
+				throttled: 2,
+				average: 0.5,
+			}
+		);
+		assert!(report
+			.render()
+			.contains("4 requests, 1 errors, 2 throttled, average"));
+	}
+
+	#[test]
+	fn into_report_should_stream_without_successes() {
+		let reporter = Reporter {
+			total_requests: 5,
+			streaming: Some(StreamingLatencies::default()),
+			..Reporter::default()
+		};
+		let report = reporter.into_report(Duration::from_secs(1), &[50_f64]);
+		assert_eq!((report.fastest, report.slowest), (0_f64, 0_f64));
+		assert!(report.latency_dist.is_empty());
+		assert!(report.render().contains("Fastest:  0 secs"));
+	}
+
+	#[test]
+	fn into_report_should_stream_close_to_exact() {
+		let responses: Vec<(u16, f64)> = (1..=1000)
+			.map(|i| (if i % 10 == 0 { 500 } else { 200 }, i as f64 / 1000_f64))
+			.collect();
+		let mut latencies = StreamingLatencies::default();
+		for (status_code, duration) in &responses {
+			latencies.record(*status_code, *duration);
+		}
+		let reporter = |responses, streaming| Reporter {
+			total_requests: 1000,
+			success_requests: 1000,
+			responses,
+			streaming,
+			..Reporter::default()
+		};
+		let pctls = [50_f64, 99.0];
+		let exact = reporter(responses, None).into_report(Duration::from_secs(1), &pctls);
+		let streamed =
+			reporter(vec![], Some(latencies)).into_report(Duration::from_secs(1), &pctls);
+		let close = |a: f64, b: f64| (a - b).abs() <= b * 0.001 + 1e-9;
+		assert_eq!(streamed.fastest, exact.fastest);
+		assert_eq!(streamed.slowest, exact.slowest);
+		assert!(close(streamed.average, exact.average));
+		assert!(close(streamed.stddev, exact.stddev));
+		for (a, b) in [
+			(streamed.p50, exact.p50),
+			(streamed.p99, exact.p99),
+			(streamed.iqr, exact.iqr),
+			(
+				streamed.latency_dist[1].latency,
+				exact.latency_dist[1].latency,
+			),
+			(
+				streamed.status_latency[&500].p99,
+				exact.status_latency[&500].p99,
+			),
+			(
+				streamed.status_latency[&200].average,
+				exact.status_latency[&200].average,
+			),
+		] {
+			assert!(close(a, b), "{} is not close to {}", a, b);
+		}
+		assert_eq!(streamed.status_latency[&500].count, 100);
+		let counts = |report: &Report| -> Vec<u64> {
+			report.histogram.iter().map(|bucket| bucket.count).collect()
+		};
+		assert_eq!(counts(&streamed).iter().sum::<u64>(), 1000);
+		assert_eq!(counts(&streamed).len(), counts(&exact).len());
+	}
+}
@@ -0,0 +1,95 @@
+use crate::report::Report;
+
+/// Thresholds a run must meet, any violation fails it.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Sla {
+	/// Maximum p99 latency in seconds
+	pub max_p99: Option<f64>,
+	/// Maximum percentage of requests that errored
+	pub max_error_rate: Option<f64>,
+	/// Minimum requests per second
+	pub min_rps: Option<f64>,
+}
+
+impl Sla {
+	/// Describe every threshold the report doesn't meet.
+	pub fn violations(&self, report: &Report) -> Vec<String> {
+		let mut violations = vec![];
+		if let Some(max) = self.max_p99 {
+			if report.p99 > max {
+				violations.push(format!(
+					"p99 latency {:.4} secs is above {} secs",
+					report.p99, max
+				));
+			}
+		}
+		if let Some(max) = self.max_error_rate {
+			if report.error_rate > max {
+				violations.push(format!(
+					"error rate {:.2}% is above {}%",
+					report.error_rate, max
+				));
+			}
+		}
+		if let Some(min) = self.min_rps {
+			if report.rps < min {
+				violations.push(format!("requests/sec {:.4} is below {}", report.rps, min));
+			}
+		}
+		violations
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::report::Report;
+	use crate::sla::Sla;
+
+	fn report() -> Report {
+		Report {
+			p99: 0.5,
+			rps: 100_f64,
+			total_requests: 100,
+			num_res: 95,
+			error_rate: 5_f64,
+			..Report::default()
+		}
+	}
+
+	#[test]
+	fn violations_should_be_empty_when_met() {
+		assert!(Sla::default().violations(&report()).is_empty());
+		let sla = Sla {
+			max_p99: Some(0.5),
+			max_error_rate: Some(5_f64),
+			min_rps: Some(100_f64),
+		};
+		assert!(sla.violations(&report()).is_empty());
+	}
+
+	#[test]
+	fn violations_should_name_every_violated_sla() {
+		let sla = Sla {
+			max_p99: Some(0.1),
+			max_error_rate: Some(1_f64),
+			min_rps: Some(200_f64),
+		};
+		assert_eq!(
+			sla.violations(&report()),
+			vec![
+				"p99 latency 0.5000 secs is above 0.1 secs",
+				"error rate 5.00% is above 1%",
+				"requests/sec 100.0000 is below 200",
+			]
+		);
+	}
+
+	#[test]
+	fn violations_should_not_divide_by_zero() {
+		let sla = Sla {
+			max_error_rate: Some(0_f64),
+			..Sla::default()
+		};
+		assert!(sla.violations(&Report::default()).is_empty());
+	}
+}
@@ -0,0 +1,162 @@
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use anyhow::{anyhow, Result};
+use rand::Rng;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Segment {
+	Literal(String),
+	/// Sequence number of the request, shared by all workers and starting at 0
+	Seq,
+	/// A random version 4 UUID
+	Uuid,
+	/// A random integer in the inclusive range
+	RandInt(i64, i64),
+}
+
+/// A request body with `{{seq}}`, `{{uuid}}` and `{{rand_int(min,max)}}`
+/// tokens, expanded for every request.
+#[derive(Debug)]
+pub struct BodyTemplate {
+	segments: Vec<Segment>,
+	seq: AtomicU64,
+}
+
+impl Clone for BodyTemplate {
+	fn clone(&self) -> Self {
+		BodyTemplate {
+			segments: self.segments.clone(),
+			seq: AtomicU64::new(self.seq.load(Ordering::Relaxed)),
+		}
+	}
+}
+
+fn parse_token(token: &str) -> Result<Segment> {
+	let err = || anyhow!("invalid template token {{{{{}}}}}", token);
+	match token {
+		"seq" => Ok(Segment::Seq),
+		"uuid" => Ok(Segment::Uuid),
+		_ => {
+			let args = token
+				.strip_prefix("rand_int(")
+				.and_then(|rest| rest.strip_suffix(')'))
+				.ok_or_else(err)?;
+			let (min, max) = args.split_once(',').ok_or_else(err)?;
+			let min = min.trim().parse::<i64>().map_err(|_| err())?;
+			let max = max.trim().parse::<i64>().map_err(|_| err())?;
+			if min > max {
+				return Err(err());
+			}
+			Ok(Segment::RandInt(min, max))
+		}
+	}
+}
+
+fn uuid<R: Rng>(rng: &mut R) -> String {
+	let mut bytes: [u8; 16] = rng.gen();
+	bytes[6] = (bytes[6] & 0x0f) | 0x40;
+	bytes[8] = (bytes[8] & 0x3f) | 0x80;
+	let mut string = String::with_capacity(36);
+	for (i, byte) in bytes.iter().enumerate() {
+		if matches!(i, 4 | 6 | 8 | 10) {
+			string.push('-');
+		}
+		write!(string, "{:02x}", byte).unwrap();
+	}
+	string
+}
+
+impl BodyTemplate {
+	pub fn parse(s: &str) -> Result<Self> {
+		let mut segments = vec![];
+		let mut rest = s;
+		while let Some(start) = rest.find("{{") {
+			let end = rest[start..]
+				.find("}}")
+				.map(|end| start + end)
+				.ok_or_else(|| anyhow!("unclosed template token"))?;
+			if start > 0 {
+				segments.push(Segment::Literal(rest[..start].to_string()));
+			}
+			segments.push(parse_token(rest[start + 2..end].trim())?);
+			rest = &rest[end + 2..];
+		}
+		if !rest.is_empty() {
+			segments.push(Segment::Literal(rest.to_string()));
+		}
+		Ok(BodyTemplate {
+			segments,
+			seq: AtomicU64::new(0),
+		})
+	}
+
+	/// Whether there is anything to expand, a static body is sent as is otherwise
+	pub fn has_tokens(&self) -> bool {
+		self.segments
+			.iter()
+			.any(|segment| !matches!(segment, Segment::Literal(_)))
+	}
+
+	pub fn render<R: Rng>(&self, rng: &mut R) -> String {
+		let seq = self.seq.fetch_add(1, Ordering::Relaxed);
+		let mut string = String::new();
+		for segment in &self.segments {
+			match segment {
+				Segment::Literal(literal) => string.push_str(literal),
+				Segment::Seq => write!(string, "{}", seq).unwrap(),
+				Segment::Uuid => string.push_str(&uuid(rng)),
+				Segment::RandInt(min, max) => {
+					write!(string, "{}", rng.gen_range(*min..=*max)).unwrap()
+				}
+			}
+		}
+		string
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use rand::rngs::StdRng;
+	use rand::SeedableRng;
+
+	use crate::template::BodyTemplate;
+
+	#[test]
+	fn render_should_expand_tokens() {
+		let mut rng = StdRng::seed_from_u64(0);
+		let template =
+			BodyTemplate::parse(r#"{"id": {{seq}}, "n": {{ rand_int(1, 3) }}, "u": "{{uuid}}"}"#)
+				.unwrap();
+		assert!(template.has_tokens());
+		for seq in 0..10 {
+			let body = template.render(&mut rng);
+			let value: serde_json::Value = serde_json::from_str(&body).unwrap();
+			assert_eq!(value["id"], seq);
+			assert!((1..=3).contains(&value["n"].as_i64().unwrap()));
+			let uuid = value["u"].as_str().unwrap();
+			assert_eq!(uuid.len(), 36);
+			assert_eq!(&uuid[14..15], "4");
+		}
+	}
+
+	#[test]
+	fn parse_should_keep_static_body() {
+		let template = BodyTemplate::parse("hello world").unwrap();
+		assert!(!template.has_tokens());
+		assert_eq!(
+			template.render(&mut StdRng::seed_from_u64(0)),
+			"hello world"
+		);
+	}
+
+	#[test]
+	fn parse_should_reject_invalid_token() {
+		assert_eq!(
+			BodyTemplate::parse("{{nope}}").unwrap_err().to_string(),
+			"invalid template token {{nope}}"
+		);
+		assert!(BodyTemplate::parse("{{rand_int(3,1)}}").is_err());
+		assert!(BodyTemplate::parse("{{seq").is_err());
+	}
+}
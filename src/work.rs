@@ -1,127 +1,1185 @@
-use std::collections::HashMap;
-use std::sync::Arc;
+use std::borrow::Cow;
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeMap, HashMap};
+use std::io::Write;
+use std::net::{IpAddr, SocketAddr};
+use std::ops::RangeInclusive;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
 
-use http::Method;
-use log::info;
-use reqwest::{Body, Client, Url};
+use anyhow::anyhow;
+use bytes::{Bytes, BytesMut};
+use futures::{stream, Stream, TryStreamExt};
+use hdrhistogram::Histogram;
+use http::header::{
+	AUTHORIZATION, CONTENT_ENCODING, ETAG, IF_NONE_MATCH, USER_AGENT, WWW_AUTHENTICATE,
+};
+use http::{HeaderMap, HeaderValue, Method, Version};
+use hyper::client::connect::HttpInfo;
+use log::{info, warn};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use regex::Regex;
+use reqwest::{Body, Client, Request, Url};
+use tokio::io::AsyncReadExt;
 use tokio::sync::mpsc::{channel, Sender};
-use tokio::sync::Notify;
+use tokio::sync::{Notify, Semaphore};
 use tokio::time::Instant;
 
-use crate::report::Reporter;
+use crate::body_dir::BodyDir;
+use crate::client::{timed_dns, ClientBuilder, IpFamily};
+use crate::compress::decompress;
+use crate::digest::{cnonce, Challenge};
+use crate::multipart::MultipartForm;
+use crate::rate::RateLimiter;
+use crate::report::{Event, Record, Reporter, StreamingLatencies};
+use crate::template::BodyTemplate;
 
 #[derive(Debug)]
 struct SourceStat {
 	pub duration: Duration,
+	/// Time spent resolving names, zero when a pooled connection was reused
+	pub dns: Duration,
+	/// Time until the response headers arrived, includes connect and TLS
+	pub ttfb: Duration,
+	/// Status of the final response, after the redirects followed
 	pub status_code: u16,
-	pub content_length: u64,
+	/// Number of body bytes received, after decompression unless the client
+	/// keeps responses compressed to count the bytes on the wire. Only the
+	/// Content-Length of bodies that aren't read
+	pub size: u64,
+	/// Number of attempts before this one
+	pub retries: u32,
+	/// Whether a digest challenge was requested before, that request isn't measured
+	pub handshake: bool,
+	/// Whether redirects were followed, the final url isn't the requested one then
+	pub redirected: bool,
+	/// Local address of the connection the response came on, which tells the
+	/// connections apart unless the OS reuses the port. Unknown through a socks proxy
+	pub local_addr: Option<SocketAddr>,
+	/// Why the response doesn't meet the expectations, it counts as an error then
+	pub failure: Option<String>,
 }
 
 type RequestResult = Result<SourceStat, reqwest::Error>;
 
+/// How failed requests are retried before the result is recorded
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Retry {
+	pub retries: u32,
+	/// Pause before the first retry, doubled for every following one
+	pub backoff: Duration,
+	/// Also retry responses with a 5xx status code
+	pub on_5xx: bool,
+}
+
+impl Retry {
+	fn should_retry(&self, result: &RequestResult) -> bool {
+		match result {
+			Err(err) => err.is_connect() || err.is_timeout(),
+			Ok(stat) => self.on_5xx && stat.status_code >= 500,
+		}
+	}
+
+	/// Pause before the retry after `retries` ones, saturating instead of overflowing
+	fn backoff_of(&self, retries: u32) -> Duration {
+		self.backoff
+			.checked_mul(2_u32.saturating_pow(retries))
+			.unwrap_or(Duration::MAX)
+	}
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct BasicAuth {
 	pub username: String,
 	pub password: Option<String>,
+	/// Answer digest challenges instead of sending the credentials upfront
+	pub digest: bool,
 }
 
+/// A prepared request, the method, url and body sent along with extra headers
+#[derive(Debug, Clone)]
+pub struct Target<B> {
+	pub method: Method,
+	pub url: Url,
+	pub headers: HeaderMap,
+	pub body: B,
+}
+
+/// A set of status codes, like 200,201,2xx
+#[derive(Debug, Clone, PartialEq)]
+pub struct StatusSet(pub Vec<RangeInclusive<u16>>);
+
+impl StatusSet {
+	pub fn contains(&self, status_code: u16) -> bool {
+		self.0.iter().any(|range| range.contains(&status_code))
+	}
+}
+
+/// What a response must look like to count as a success
+#[derive(Debug, Clone, Default)]
+pub struct Expect {
+	pub status: Option<StatusSet>,
+	/// Substring the body must contain
+	pub body: Option<String>,
+	/// Pattern the body must match
+	pub body_regex: Option<Regex>,
+}
+
+impl Expect {
+	fn needs_body(&self) -> bool {
+		self.body.is_some() || self.body_regex.is_some()
+	}
+
+	fn check(&self, status_code: u16, body: &[u8]) -> Option<String> {
+		if let Some(status) = &self.status {
+			if !status.contains(status_code) {
+				return Some(format!("unexpected status {}", status_code));
+			}
+		}
+		if !self.needs_body() {
+			return None;
+		}
+		let body = String::from_utf8_lossy(body);
+		if let Some(expect) = &self.body {
+			if !body.contains(expect.as_str()) {
+				return Some(format!(
+					"assertion failed: body doesn't contain {:?}",
+					expect
+				));
+			}
+		}
+		if let Some(regex) = &self.body_regex {
+			if !regex.is_match(&body) {
+				return Some(format!("assertion failed: body doesn't match /{}/", regex));
+			}
+		}
+		None
+	}
+}
+
+/// Builds the client of a worker anew
+type NewClient = Arc<dyn Fn() -> anyhow::Result<Client> + Send + Sync>;
+
+/// Builds a client bound to a source address, or to none
+pub type ClientHook = Arc<dyn Fn(Option<IpAddr>) -> anyhow::Result<Client> + Send + Sync>;
+
 struct Worker<B>
 where
-	B: Into<Body> + Copy,
+	B: Into<Body> + Clone,
 {
-	url: Url,
-	method: Method,
+	targets: Vec<Target<B>>,
+	/// Generates the body of every request instead of the body of the target
+	template: Option<Arc<BodyTemplate>>,
+	/// Sent as the body of every request instead of the body of the target
+	multipart: Option<Arc<MultipartForm>>,
+	/// Streamed from disk as the body of every request instead of the body of the target
+	body_file: Option<Arc<PathBuf>>,
+	/// Picked from as the body of every request instead of the body of the target
+	body_dir: Option<Arc<BodyDir>>,
+	/// Cumulative weights of `targets`, picked at random instead of in turn
+	weights: Option<Arc<Vec<u64>>>,
+	/// Offset into `targets` of the first request, so workers don't all start on the same one
+	offset: usize,
 	basic_auth: Option<BasicAuth>,
+	bearer: Option<String>,
+	version: Option<Version>,
+	/// Name of a random query parameter appended to every request
+	cache_bust: Option<String>,
 	rate_limit: Option<f64>,
-	body: B,
-	requests: u64,
+	/// Send on the schedule of `rate_limit` and count latencies from the time
+	/// a request was due
+	correct_co: bool,
+	limiter: Option<Arc<RateLimiter>>,
+	/// Requests in flight of all workers, so they don't open more connections
+	connections: Option<Arc<Semaphore>>,
+	think: Option<Duration>,
+	think_jitter: Duration,
+	retry: Retry,
+	expect: Arc<Expect>,
+	/// Don't read response bodies
+	skip_body: bool,
+	/// Don't read response bodies nor count their size
+	ttfb_only: bool,
+	/// Sent in turn as the User-Agent of the requests of all workers instead
+	/// of that of the client
+	user_agents: Arc<Vec<HeaderValue>>,
+	/// Requests that took a turn of `user_agents`
+	user_agent_turns: Arc<AtomicU64>,
+	/// Requests sent with a client before it's replaced by a new one, along
+	/// with what builds it, so its connection is dropped and another opened
+	reconnect: Option<(u64, NewClient)>,
+	/// ETag of the last response of every target url, before cache busting,
+	/// sent back as If-None-Match. Only kept when asked for
+	etags: Option<Mutex<HashMap<Url, HeaderValue>>>,
+	/// Requests to send, until the run is stopped when not given
+	requests: Option<u64>,
+	/// Set once the run is cancelled, no request is started afterwards
+	stop: Arc<AtomicBool>,
+	/// Requests started by all workers, whether completed or not
+	issued: Arc<AtomicU64>,
+	/// Source of every random choice of the worker
+	rng: Mutex<StdRng>,
 	client: Arc<Client>,
-	sender: Sender<RequestResult>,
+	/// Index of the worker, its results are tagged with it
+	id: u16,
+	/// Results tagged with the worker and the index of the target requested
+	sender: Sender<(u16, usize, RequestResult)>,
 }
 
 impl<B> Worker<B>
 where
-	B: Into<Body> + Copy,
+	B: Into<Body> + Clone,
 {
-	async fn make_request(&self) -> RequestResult {
-		let start = Instant::now();
+	fn build_request(
+		&self,
+		target: &Target<B>,
+		url: &Url,
+		body: Option<&Bytes>,
+		user_agent: Option<&HeaderValue>,
+		authorization: Option<&str>,
+	) -> Result<Request, reqwest::Error> {
+		let method = target.method.clone();
+		let mut builder = self
+			.client
+			.request(method, url.clone())
+			.headers(target.headers.clone());
+		match (&self.basic_auth, authorization) {
+			(_, Some(authorization)) => builder = builder.header(AUTHORIZATION, authorization),
+			(Some(auth), None) if !auth.digest => {
+				builder = builder.basic_auth(auth.username.clone(), auth.password.clone());
+			}
+			_ => {}
+		}
+		if let Some(token) = &self.bearer {
+			builder = builder.bearer_auth(token);
+		}
+		if let Some(version) = self.version {
+			builder = builder.version(version);
+		}
+		if let Some(user_agent) = user_agent {
+			builder = builder.header(USER_AGENT, user_agent);
+		}
+		if let Some(etag) = self
+			.etags
+			.as_ref()
+			.and_then(|etags| etags.lock().unwrap().get(&target.url).cloned())
+		{
+			builder = builder.header(IF_NONE_MATCH, etag);
+		}
+		match (&self.multipart, &self.body_file, body) {
+			(Some(form), _, _) => builder.multipart(form.build()),
+			(None, Some(path), _) => {
+				builder.body(hyper::Body::wrap_stream(file_stream(path.clone())))
+			}
+			(None, None, Some(body)) => builder.body(body.clone()),
+			(None, None, None) => builder.body(target.body.clone()),
+		}
+		.build()
+	}
 
-		// build
-		let client = self.client.clone();
-		let method = self.method.clone();
-		let url = self.url.clone();
-		let mut builder = client.request(method, url);
-		if let Some(auth) = self.basic_auth.clone() {
-			builder = builder.basic_auth(auth.username, auth.password);
-		}
-		// request
-		let request = builder.body(self.body).build()?;
-		let response = client.execute(request).await?;
+	/// Send the request of `target` and time it, along with the challenge of a 401 response
+	async fn send(
+		&self,
+		target: &Target<B>,
+		request: Request,
+	) -> Result<(SourceStat, Option<String>), reqwest::Error> {
+		let url = request.url().clone();
+		// HEAD responses have no body to read, whatever their Content-Length
+		let skip_body = self.skip_body || self.ttfb_only || request.method() == Method::HEAD;
+		let start = Instant::now();
+		let (response, dns) = timed_dns(self.client.execute(request)).await;
+		let response = response?;
+		let redirected = *response.url() != url;
+		let local_addr = response
+			.extensions()
+			.get::<HttpInfo>()
+			.map(HttpInfo::local_addr);
+		let ttfb = start.elapsed();
 		let status_code = response.status().as_u16();
-		let content_length = response.content_length().unwrap_or(0);
-		let _res = response.bytes().await?;
-		Ok(SourceStat {
-			duration: start.elapsed(),
+		if let (Some(etags), Some(etag)) = (&self.etags, response.headers().get(ETAG)) {
+			// by the url of the target, a cache-busted one is never requested again
+			etags
+				.lock()
+				.unwrap()
+				.insert(target.url.clone(), etag.clone());
+		}
+		let challenge = response
+			.headers()
+			.get(WWW_AUTHENTICATE)
+			.filter(|_| status_code == 401)
+			.and_then(|value| value.to_str().ok())
+			.map(str::to_string);
+		// only set when the client left the body compressed
+		let encoding = response
+			.headers()
+			.get(CONTENT_ENCODING)
+			.and_then(|value| value.to_str().ok())
+			.map(str::to_string);
+		let size = match self.ttfb_only {
+			true => 0,
+			false => response.content_length().unwrap_or_default(),
+		};
+		// the connection is closed instead of reused when the body is dropped unread
+		let (body, duration) = if skip_body {
+			(Bytes::new(), ttfb)
+		} else {
+			(response.bytes().await?, start.elapsed())
+		};
+		let decoded = match encoding.filter(|_| self.expect.needs_body()) {
+			Some(encoding) => decompress(&encoding, &body).map(Cow::Owned),
+			None => None,
+		}
+		.unwrap_or(Cow::Borrowed(&body[..]));
+		let stat = SourceStat {
+			duration,
+			dns,
+			ttfb,
 			status_code,
-			content_length,
+			size: if skip_body { size } else { body.len() as u64 },
+			retries: 0,
+			handshake: false,
+			redirected,
+			local_addr,
+			failure: self.expect.check(status_code, &decoded),
+		};
+		Ok((stat, challenge))
+	}
+
+	async fn make_request(&self, target: &Target<B>) -> RequestResult {
+		// waiting for a connection isn't part of the latency
+		let _permit = match &self.connections {
+			Some(connections) => Some(
+				connections
+					.acquire()
+					.await
+					.expect("the connection semaphore is never closed"),
+			),
+			None => None,
+		};
+		let body = match (&self.template, &self.body_dir) {
+			(Some(template), _) => {
+				Some(Bytes::from(template.render(&mut *self.rng.lock().unwrap())))
+			}
+			(None, Some(body_dir)) => Some(body_dir.pick(&mut *self.rng.lock().unwrap())),
+			(None, None) => None,
+		};
+		let mut url = target.url.clone();
+		if let Some(param) = &self.cache_bust {
+			cache_bust(&mut url, param, &mut *self.rng.lock().unwrap());
+		}
+		let user_agent = (!self.user_agents.is_empty()).then(|| {
+			let turn = self.user_agent_turns.fetch_add(1, Ordering::Relaxed);
+			&self.user_agents[turn as usize % self.user_agents.len()]
+		});
+		let request = self.build_request(target, &url, body.as_ref(), user_agent, None)?;
+		let (stat, challenge) = self.send(target, request).await?;
+		// digest auth answers the challenge of the first response, which isn't measured
+		let (auth, challenge) = match (&self.basic_auth, challenge) {
+			(Some(auth), Some(challenge)) if auth.digest => (auth, challenge),
+			_ => return Ok(stat),
+		};
+		let challenge = match Challenge::parse(&challenge) {
+			Ok(challenge) => challenge,
+			Err(err) => {
+				return Ok(SourceStat {
+					failure: Some(err.to_string()),
+					..stat
+				})
+			}
+		};
+		let uri = match url.query() {
+			Some(query) => format!("{}?{}", url.path(), query),
+			None => url.path().to_string(),
+		};
+		let authorization = challenge.authorization(
+			&auth.username,
+			auth.password.as_deref().unwrap_or_default(),
+			target.method.as_str(),
+			&uri,
+			&cnonce(&mut *self.rng.lock().unwrap()),
+		);
+		let request = self.build_request(
+			target,
+			&url,
+			body.as_ref(),
+			user_agent,
+			Some(&authorization),
+		)?;
+		let (stat, _) = self.send(target, request).await?;
+		Ok(SourceStat {
+			handshake: true,
+			..stat
 		})
 	}
 
-	async fn execute(&self) {
+	async fn make_request_with_retry(&self, target: &Target<B>) -> RequestResult {
+		let mut retries = 0;
+		loop {
+			let result = self.make_request(target).await;
+			if retries >= self.retry.retries || !self.retry.should_retry(&result) {
+				return result.map(|stat| SourceStat { retries, ..stat });
+			}
+			tokio::time::sleep(self.retry.backoff_of(retries)).await;
+			retries += 1;
+		}
+	}
+
+	async fn execute(mut self) {
 		let interval = self
 			.rate_limit
-			.map(|qps| (1000000_f64 / qps).floor() as u64);
-		for _ in 0..self.requests {
+			.map(|qps| Duration::from_secs_f64(1_f64 / qps));
+		// when the last request was due, the fractions of a rate below 1 add up instead of being rounded away
+		let mut due = Instant::now();
+		let mut i = 0;
+		while self.requests.is_none_or(|requests| i < requests) {
+			if let Some(think) = self.think.filter(|_| i > 0) {
+				let pause = think_time(think, self.think_jitter, &mut *self.rng.lock().unwrap());
+				tokio::time::sleep(pause).await;
+			}
+			let mut late = None;
 			if let Some(interval) = interval {
-				tokio::time::sleep(Duration::from_micros(interval)).await;
+				// a request delayed by a slow one is due on schedule when correcting
+				// coordinated omission so its latency counts from then, later otherwise
+				due = if self.correct_co {
+					due + interval
+				} else {
+					(due + interval).max(Instant::now())
+				};
+				tokio::time::sleep_until(due).await;
+				late = self.correct_co.then_some(due);
 			}
-			let result = self.make_request().await;
+			if let Some(limiter) = &self.limiter {
+				limiter.acquire().await;
+			}
+			if self.stop.load(Ordering::Relaxed) {
+				info!("worker stopped due to cancellation");
+				return;
+			}
+			if let Some((requests, new_client)) = &self.reconnect {
+				if i > 0 && i.is_multiple_of(*requests) {
+					match new_client() {
+						Ok(client) => self.client = Arc::new(client),
+						Err(err) => warn!("keep the client, fail to build another: {}", err),
+					}
+				}
+			}
+			let index = match &self.weights {
+				Some(cumulative) => pick_weighted(cumulative, &mut *self.rng.lock().unwrap()),
+				None => (self.offset + i as usize) % self.targets.len(),
+			};
+			self.issued.fetch_add(1, Ordering::Relaxed);
+			let late = late.map(|due| due.elapsed()).unwrap_or_default();
+			let result = self
+				.make_request_with_retry(&self.targets[index])
+				.await
+				.map(|stat| SourceStat {
+					duration: stat.duration + late,
+					..stat
+				});
 			let sender = self.sender.clone();
-			if let Err(error) = sender.send(result).await {
+			if let Err(error) = sender.send((self.id, index, result)).await {
 				info!("worker interrupt due to error:{}", error);
 				return;
 			}
+			i += 1;
 		}
 	}
 }
 
+impl<B> Worker<B>
+where
+	B: Into<Body> + Clone + Send + Sync + 'static,
+{
+	/// Start a request every `interval` whether the previous ones completed or
+	/// not, up to `in_flight` at once. Latencies count from the scheduled time,
+	/// so waiting for a slot is part of them.
+	async fn execute_open_loop(self: Arc<Self>, interval: Duration, in_flight: Arc<Semaphore>) {
+		let mut schedule = tokio::time::interval(interval);
+		let mut i = 0;
+		while self.requests.is_none_or(|requests| i < requests) {
+			// the instant the request was due, later ones catch up after a pause
+			let scheduled = schedule.tick().await;
+			let Ok(permit) = in_flight.clone().acquire_owned().await else {
+				return;
+			};
+			if self.stop.load(Ordering::Relaxed) || self.sender.is_closed() {
+				info!("open loop stopped due to cancellation");
+				return;
+			}
+			let index = match &self.weights {
+				Some(cumulative) => pick_weighted(cumulative, &mut *self.rng.lock().unwrap()),
+				None => i as usize % self.targets.len(),
+			};
+			self.issued.fetch_add(1, Ordering::Relaxed);
+			let worker = self.clone();
+			tokio::spawn(async move {
+				let delay = scheduled.elapsed();
+				let result = worker
+					.make_request_with_retry(&worker.targets[index])
+					.await
+					.map(|stat| SourceStat {
+						duration: stat.duration + delay,
+						..stat
+					});
+				drop(permit);
+				if let Err(error) = worker.sender.send((worker.id, index, result)).await {
+					info!("open loop interrupt due to error:{}", error);
+				}
+			});
+			i += 1;
+		}
+	}
+}
+
+/// The progress line, with the median and p99 of the `latencies` of the last
+/// second, in microseconds, when there were responses
+fn progress_line(
+	total: u64,
+	rps: u64,
+	errors: u64,
+	latencies: &Histogram<u64>,
+	elapsed: Duration,
+) -> String {
+	let mut line = format!("{} requests, {} req/s, {} errors", total, rps, errors);
+	if !latencies.is_empty() {
+		let secs = |p: f64| latencies.value_at_percentile(p) as f64 / 1_000_000_f64;
+		line += &format!(
+			", p50 {:.4} secs, p99 {:.4} secs",
+			secs(50_f64),
+			secs(99_f64)
+		);
+	}
+	line + &format!(", {}s elapsed", elapsed.as_secs())
+}
+
+fn print_progress(line: &str) {
+	eprint!("\r\x1b[2K{}", line);
+}
+
+fn clear_progress() {
+	eprint!("\r\x1b[2K");
+}
+
+/// A stable name for the kind of error, the messages themselves vary with the
+/// OS, addresses and ports.
+fn error_category(err: &reqwest::Error) -> &'static str {
+	let mut source = std::error::Error::source(err);
+	while let Some(cause) = source {
+		let message = cause.to_string().to_ascii_lowercase();
+		// hyper names resolution failures the same on every platform, the cause after it doesn't
+		if message.starts_with("dns error") {
+			return "dns";
+		}
+		// the server dropped an established connection, often a kept-alive one, mid-exchange
+		if !err.is_connect()
+			&& [
+				"connection closed before message completed",
+				"end of file before message length reached",
+				"unexpected eof during chunk",
+				"connection reset",
+			]
+			.iter()
+			.any(|phrase| message.contains(phrase))
+		{
+			return "conn-reset";
+		}
+		if ["certificate", "tls", "ssl", "handshake"]
+			.iter()
+			.any(|word| message.contains(word))
+		{
+			return "tls";
+		}
+		source = cause.source();
+	}
+	if err.is_timeout() {
+		"timeout"
+	} else if err.is_connect() {
+		"connect"
+	} else if err.is_redirect() {
+		"redirect"
+	} else if err.is_decode() {
+		"decode"
+	} else {
+		"other"
+	}
+}
+
+/// Read the file in chunks, it's opened once the request starts sending its body
+fn file_stream(path: Arc<PathBuf>) -> impl Stream<Item = std::io::Result<Bytes>> {
+	stream::once(async move { tokio::fs::File::open(path.as_path()).await })
+		.map_ok(|file| {
+			stream::try_unfold(file, |mut file| async move {
+				let mut chunk = BytesMut::with_capacity(64 * 1024);
+				let read = file.read_buf(&mut chunk).await?;
+				Ok((read > 0).then(|| (chunk.freeze(), file)))
+			})
+		})
+		.try_flatten()
+}
+
+/// Append `param` with a random value to the query of `url`, after any
+/// existing parameter of the same name.
+fn cache_bust<R: Rng>(url: &mut Url, param: &str, rng: &mut R) {
+	let value = format!("{:016x}", rng.gen::<u64>());
+	url.query_pairs_mut().append_pair(param, &value);
+}
+
+/// Pick an index at random according to the `cumulative` weights.
+pub(crate) fn pick_weighted<R: Rng>(cumulative: &[u64], rng: &mut R) -> usize {
+	let total = *cumulative.last().unwrap();
+	let r = rng.gen_range(0..total);
+	cumulative.partition_point(|&c| c <= r)
+}
+
+/// A random pause in `base ± jitter`, never below zero.
+fn think_time<R: Rng>(base: Duration, jitter: Duration, rng: &mut R) -> Duration {
+	if jitter.is_zero() {
+		return base;
+	}
+	let offset = rng.gen_range(-jitter.as_secs_f64()..=jitter.as_secs_f64());
+	Duration::from_secs_f64((base.as_secs_f64() + offset).max(0_f64))
+}
+
+/// Number of requests the worker at `index` should send, the remainder of
+/// `total / workers` goes to the first workers so nothing is dropped.
+fn requests_of_worker(total: u64, workers: u16, index: u16) -> u64 {
+	let workers = workers as u64;
+	let index = index as u64;
+	let requests = total / workers;
+	if index < total % workers {
+		requests + 1
+	} else {
+		requests
+	}
+}
+
+#[derive(Clone)]
 pub struct Work<C, B>
 where
-	C: TryInto<Client, Error = anyhow::Error>,
-	B: Into<Body> + Copy + Send + Sync + 'static,
+	C: TryInto<Client, Error = anyhow::Error> + Clone,
+	B: Into<Body> + Clone + Send + Sync + 'static,
 {
 	pub client_builder: C,
-	pub url: Url,
-	pub method: Method,
+	/// Build a client for every worker instead of sharing one, so connections
+	/// and cookies aren't shared either
+	pub client_per_worker: bool,
+	/// Count the results and latencies of every worker
+	pub per_worker: bool,
+	/// Source addresses the workers are assigned in turn. There is a client
+	/// for every address, so connections and cookies are only shared by the
+	/// workers with the same address, and each address opens its own
+	/// connections to a host
+	pub local_addresses: Vec<IpAddr>,
+	/// Builds the clients instead of `client_builder`, required by
+	/// `local_addresses` and `requests_per_conn`. Set by the WorkBuilder
+	pub client_hook: Option<ClientHook>,
+	/// Sent in turn as the User-Agent of the requests, that of the client
+	/// when empty
+	pub user_agents: Vec<HeaderValue>,
+	/// Requests sent in turn by every worker
+	pub targets: Vec<Target<B>>,
+	/// Body generated for every request, replaces the body of the targets
+	pub template: Option<BodyTemplate>,
+	/// Multipart body of every request, replaces the body of the targets
+	pub multipart: Option<MultipartForm>,
+	/// File streamed as the body of every request, reopened for each one instead
+	/// of being loaded into memory. Replaces the body of the targets
+	pub body_file: Option<PathBuf>,
+	/// Files one of which is picked at random as the body of every request,
+	/// replaces the body of the targets
+	pub body_dir: Option<BodyDir>,
+	/// Weights of `targets`, which are picked at random when given instead of in turn
+	pub weights: Option<Vec<u64>>,
 	pub auth: Option<BasicAuth>,
+	pub bearer: Option<String>,
+	/// Protocol version of every request, must match the client configuration
+	pub version: Option<Version>,
+	/// Append a random query parameter with this name to every request, an
+	/// existing parameter with the same name is kept
+	pub cache_bust: Option<String>,
 	pub workers: u16,
+	/// Requests in flight at once across workers, so no more connections are
+	/// opened whatever the number of workers. The client should keep as many
+	/// idle connections
+	pub max_connections: Option<usize>,
+	/// Requests every worker sends on a client before replacing it with a new
+	/// one, whose connection is then opened anew. Workers get a client each
+	pub requests_per_conn: Option<u64>,
+	/// Results buffered between the workers and the aggregation, workers wait
+	/// for room once it is full
+	pub result_buffer: usize,
 	pub total_requests: u64,
+	/// Send requests until this time elapsed, or the run is cancelled before,
+	/// instead of `total_requests`
+	pub duration: Option<Duration>,
+	/// Stop once this many results were counted, even if `total_requests`
+	/// were not all sent yet
+	pub max_requests: Option<u64>,
 	pub rate_limit: Option<f64>,
-	pub body: B,
+	/// Rate limit shared by all workers, takes precedence over `rate_limit`
+	pub global_rate_limit: Option<f64>,
+	/// Start requests at the rate limit whether the previous ones completed or
+	/// not, instead of each worker waiting for its own. `workers` caps the
+	/// requests in flight, latencies count from the time a request was due
+	pub open_loop: bool,
+	/// Send the requests of every worker on the schedule of `rate_limit`, a
+	/// request delayed by a slow one before it is sent as soon as possible and
+	/// its latency counts from the time it was due. Without it a stall delays
+	/// the following requests unnoticed and hides in fewer samples
+	pub correct_co: bool,
+	/// Pause of every worker between two requests
+	pub think: Option<Duration>,
+	/// Random variation added to or removed from `think`
+	pub think_jitter: Duration,
+	pub retry: Retry,
+	/// Responses not meeting it are counted as errors
+	pub expect: Expect,
+	/// Don't read response bodies, their size is taken from Content-Length
+	/// instead. HEAD responses are never read
+	pub skip_body: bool,
+	/// Stop at the response headers and drop the bodies, so latencies are the
+	/// time to first byte and `size_total` is 0
+	pub ttfb_only: bool,
+	/// Send the ETag of the last response of a url back as If-None-Match, every
+	/// worker keeps its own
+	pub etag: bool,
+	/// Workers are started evenly over this time instead of all at once
+	pub ramp_up: Option<Duration>,
+	/// Results received during this time since the start are discarded
+	pub warmup: Option<Duration>,
+	/// Number of requests sent on top of `total_requests` whose results are discarded
+	pub warmup_requests: u64,
+	/// On cancellation, wait up to this time for requests in flight to be
+	/// counted instead of dropping them
+	pub drain_timeout: Option<Duration>,
+	/// Cancel the run once this many requests failed
+	pub max_errors: Option<u64>,
+	/// Successful responses slower than it are counted as slow, they are
+	/// still part of the latencies
+	pub slow_threshold: Option<Duration>,
+	/// A response on a connection seen before came on a new one reusing its
+	/// port when its connection setup took longer. reqwest only times the DNS
+	/// lookup of the setup, which a pooled connection skips
+	pub reuse_threshold: Duration,
+	/// Responses with these status codes are counted as throttled, neither
+	/// successes nor errors, and left out of the latencies
+	pub throttle_status: Option<StatusSet>,
+	/// Key errors by their message instead of their category
+	pub verbose_errors: bool,
+	/// Print a progress line to stderr every second, with the median and p99
+	/// latencies of that second
+	pub progress: bool,
+	/// Keep a record of every request in the `Reporter`
+	pub records: bool,
+	/// Count the results received every second of the run
+	pub timeline: bool,
+	/// Write every request as a JSON line as soon as its result is received,
+	/// flushed every second. Shared by the copies of the work
+	pub events: Option<Arc<Mutex<dyn Write + Send>>>,
+	/// Record the latencies in a HdrHistogram of the `Reporter`
+	pub hdr: bool,
+	/// Aggregate the latencies as they arrive instead of keeping every one
+	pub streaming: bool,
+	/// Seed of the random choices, from entropy when not given. The seed of
+	/// every worker is drawn in turn from a `StdRng` seeded with it, so a run
+	/// with the same seed and number of workers makes the same choices
+	pub seed: Option<u64>,
+}
+
+/// Setters of `WorkBuilder` fields
+macro_rules! setters {
+	($($name:ident: $ty:ty;)*) => {$(
+		pub fn $name(mut self, $name: $ty) -> Self {
+			self.work.$name = $name;
+			self
+		}
+	)*};
+}
+
+/// Setters of optional `WorkBuilder` fields, which take a value or an `Option`
+macro_rules! optional_setters {
+	($($name:ident: $ty:ty;)*) => {$(
+		pub fn $name(mut self, $name: impl Into<Option<$ty>>) -> Self {
+			self.work.$name = $name.into();
+			self
+		}
+	)*};
+}
+
+/// Builds a `Work`, with the defaults of the command line. Urls are requested
+/// with the method and body of the builder, targets as they are.
+pub struct WorkBuilder {
+	work: Work<ClientBuilder, Bytes>,
+	urls: Vec<Url>,
+	method: Method,
+	body: Bytes,
+}
+
+impl Default for WorkBuilder {
+	fn default() -> Self {
+		WorkBuilder {
+			work: Work {
+				client_builder: ClientBuilder::default(),
+				client_per_worker: false,
+				local_addresses: vec![],
+				client_hook: None,
+				user_agents: vec![],
+				targets: vec![],
+				template: None,
+				multipart: None,
+				body_file: None,
+				body_dir: None,
+				weights: None,
+				auth: None,
+				bearer: None,
+				version: None,
+				cache_bust: None,
+				workers: 50,
+				max_connections: None,
+				requests_per_conn: None,
+				result_buffer: 4096,
+				total_requests: 200,
+				duration: None,
+				max_requests: None,
+				rate_limit: None,
+				global_rate_limit: None,
+				open_loop: false,
+				correct_co: false,
+				think: None,
+				think_jitter: Duration::ZERO,
+				retry: Retry::default(),
+				expect: Expect::default(),
+				skip_body: false,
+				ttfb_only: false,
+				etag: false,
+				ramp_up: None,
+				warmup: None,
+				warmup_requests: 0,
+				drain_timeout: None,
+				max_errors: None,
+				slow_threshold: None,
+				reuse_threshold: Duration::ZERO,
+				throttle_status: None,
+				verbose_errors: false,
+				progress: false,
+				records: false,
+				timeline: false,
+				per_worker: false,
+				events: None,
+				hdr: false,
+				streaming: false,
+				seed: None,
+			},
+			urls: vec![],
+			method: Method::GET,
+			body: Bytes::new(),
+		}
+	}
+}
+
+impl WorkBuilder {
+	pub fn new() -> Self {
+		WorkBuilder::default()
+	}
+
+	pub fn client(mut self, client_builder: ClientBuilder) -> Self {
+		self.work.client_builder = client_builder;
+		self
+	}
+
+	pub fn url(mut self, url: Url) -> Self {
+		self.urls.push(url);
+		self
+	}
+
+	pub fn method(mut self, method: Method) -> Self {
+		self.method = method;
+		self
+	}
+
+	pub fn body(mut self, body: impl Into<Bytes>) -> Self {
+		self.body = body.into();
+		self
+	}
+
+	pub fn target(mut self, target: Target<Bytes>) -> Self {
+		self.work.targets.push(target);
+		self
+	}
+
+	pub fn targets(mut self, targets: impl IntoIterator<Item = Target<Bytes>>) -> Self {
+		self.work.targets.extend(targets);
+		self
+	}
+
+	setters! {
+		client_per_worker: bool;
+		local_addresses: Vec<IpAddr>;
+		user_agents: Vec<HeaderValue>;
+		workers: u16;
+		open_loop: bool;
+		correct_co: bool;
+		result_buffer: usize;
+		total_requests: u64;
+		think_jitter: Duration;
+		reuse_threshold: Duration;
+		retry: Retry;
+		expect: Expect;
+		skip_body: bool;
+		ttfb_only: bool;
+		etag: bool;
+		warmup_requests: u64;
+		verbose_errors: bool;
+		progress: bool;
+		records: bool;
+		timeline: bool;
+		per_worker: bool;
+		hdr: bool;
+		streaming: bool;
+	}
+
+	optional_setters! {
+		template: BodyTemplate;
+		multipart: MultipartForm;
+		body_file: PathBuf;
+		body_dir: BodyDir;
+		weights: Vec<u64>;
+		auth: BasicAuth;
+		bearer: String;
+		version: Version;
+		cache_bust: String;
+		duration: Duration;
+		max_requests: u64;
+		rate_limit: f64;
+		global_rate_limit: f64;
+		think: Duration;
+		ramp_up: Duration;
+		warmup: Duration;
+		drain_timeout: Duration;
+		events: Arc<Mutex<dyn Write + Send>>;
+		max_errors: u64;
+		slow_threshold: Duration;
+		throttle_status: StatusSet;
+		max_connections: usize;
+		requests_per_conn: u64;
+		seed: u64;
+	}
+
+	/// The work, targets first and urls after them
+	pub fn build(self) -> anyhow::Result<Work<ClientBuilder, Bytes>> {
+		let mut work = self.work;
+		let client_builder = work.client_builder.clone();
+		work.client_hook = Some(Arc::new(move |local_address| {
+			ClientBuilder {
+				local_address,
+				..client_builder.clone()
+			}
+			.try_into()
+		}));
+		work.targets.extend(self.urls.into_iter().map(|url| Target {
+			method: self.method.clone(),
+			url,
+			headers: HeaderMap::new(),
+			body: self.body.clone(),
+		}));
+		if work.targets.is_empty() {
+			return Err(anyhow!("no url to request"));
+		}
+		if work.workers == 0 {
+			return Err(anyhow!("workers must be at least one"));
+		}
+		// the requests are ignored when running for a duration
+		if work.duration.is_none() && work.total_requests < work.workers as u64 {
+			return Err(anyhow!(
+				"total_requests of {} can't keep {} workers busy, lower workers or raise total_requests",
+				work.total_requests,
+				work.workers
+			));
+		}
+		// the interval between requests must be a duration
+		for (name, rate) in [
+			("rate_limit", work.rate_limit),
+			("global_rate_limit", work.global_rate_limit),
+		] {
+			if let Some(qps) = rate.filter(|qps| {
+				!(qps.is_finite() && *qps > 0_f64)
+					|| Duration::try_from_secs_f64(1_f64 / qps).is_err()
+			}) {
+				return Err(anyhow!("{} must be a positive rate, not {}", name, qps));
+			}
+		}
+		if let Some(weights) = &work.weights {
+			if weights.len() != work.targets.len() {
+				return Err(anyhow!(
+					"{} weights for {} urls",
+					weights.len(),
+					work.targets.len()
+				));
+			}
+			// a url of weight 0 is never picked, and no url at all with only those
+			if weights.contains(&0) {
+				return Err(anyhow!("weights must be positive"));
+			}
+		}
+		Ok(work)
+	}
 }
 
 impl<C, B> Work<C, B>
 where
-	C: TryInto<Client, Error = anyhow::Error>,
-	B: Into<Body> + Copy + Send + Sync + 'static,
+	C: TryInto<Client, Error = anyhow::Error> + Clone,
+	B: Into<Body> + Clone + Send + Sync + 'static,
 {
 	pub async fn execute(self, cancel: Arc<Notify>) -> anyhow::Result<Reporter> {
-		let client = Arc::new(self.client_builder.try_into()?);
-		let requests = self.total_requests / (self.workers as u64);
-		let (sender, mut receiver) = channel(self.workers as usize);
-		for _ in 0..self.workers {
+		if self.client_hook.is_none()
+			&& (!self.local_addresses.is_empty() || self.requests_per_conn.is_some())
+		{
+			return Err(anyhow!(
+				"local_addresses and requests_per_conn require a client_hook"
+			));
+		}
+		// the source address of the worker at an index
+		let local_address = |index: u16| match self.local_addresses.len() {
+			0 => None,
+			len => Some(self.local_addresses[index as usize % len]),
+		};
+		let new_client = |index: u16| match &self.client_hook {
+			Some(hook) => hook(local_address(index)),
+			None => self.client_builder.clone().try_into(),
+		};
+		// workers share a client per address unless they get their own
+		let own_client = self.client_per_worker || self.requests_per_conn.is_some();
+		let clients = match own_client {
+			true => vec![],
+			false => (0..self.local_addresses.len().max(1) as u16)
+				.map(|index| new_client(index).map(Arc::new))
+				.collect::<anyhow::Result<Vec<Arc<Client>>>>()?,
+		};
+		let limiter = self
+			.global_rate_limit
+			.map(|qps| Arc::new(RateLimiter::new(qps)));
+		let rate_limit = if limiter.is_some() {
+			None
+		} else {
+			self.rate_limit
+		};
+		// the arrival rate of the open loop, shared by all workers
+		let arrival = match self.open_loop {
+			true => Some(
+				self.global_rate_limit
+					.or(self.rate_limit.map(|qps| qps * self.workers as f64))
+					.filter(|qps| *qps > 0_f64)
+					.ok_or_else(|| {
+						anyhow!("an open loop needs a rate limit, set -q or --rate-global")
+					})?,
+			),
+			false => None,
+		};
+		let template = self.template.map(Arc::new);
+		let multipart = self.multipart.map(Arc::new);
+		let body_file = self.body_file.map(Arc::new);
+		let body_dir = self.body_dir.map(Arc::new);
+		let expect = Arc::new(self.expect);
+		let weights = self.weights.map(|weights| {
+			Arc::new(
+				weights
+					.iter()
+					.scan(0, |sum, weight| {
+						*sum += weight;
+						Some(*sum)
+					})
+					.collect::<Vec<u64>>(),
+			)
+		});
+		let connections = self
+			.max_connections
+			.map(|max| Arc::new(Semaphore::new(max)));
+		let stop = Arc::new(AtomicBool::new(false));
+		let issued = Arc::new(AtomicU64::new(0));
+		let user_agents = Arc::new(self.user_agents);
+		let user_agent_turns = Arc::new(AtomicU64::new(0));
+		let mut seeds = match self.seed {
+			Some(seed) => StdRng::seed_from_u64(seed),
+			None => StdRng::from_entropy(),
+		};
+		// workers are started after it, the time of everything counts from it
+		let start = Instant::now();
+		let (sender, mut receiver) = channel(self.result_buffer.max(1));
+		for index in 0..self.workers {
+			let requests = self.duration.is_none().then(|| match arrival {
+				Some(_) => self.total_requests + self.warmup_requests,
+				None => requests_of_worker(
+					self.total_requests + self.warmup_requests,
+					self.workers,
+					index,
+				),
+			});
 			let worker = Worker {
-				url: self.url.clone(),
-				method: self.method.clone(),
+				targets: self.targets.clone(),
+				template: template.clone(),
+				multipart: multipart.clone(),
+				body_file: body_file.clone(),
+				body_dir: body_dir.clone(),
+				weights: weights.clone(),
+				offset: index as usize,
 				basic_auth: self.auth.clone(),
-				rate_limit: self.rate_limit,
+				bearer: self.bearer.clone(),
+				version: self.version,
+				cache_bust: self.cache_bust.clone(),
+				rate_limit: rate_limit.filter(|_| arrival.is_none()),
+				correct_co: self.correct_co,
+				limiter: limiter.clone().filter(|_| arrival.is_none()),
+				connections: connections.clone(),
+				think: self.think,
+				think_jitter: self.think_jitter,
+				retry: self.retry,
+				expect: expect.clone(),
+				skip_body: self.skip_body,
+				ttfb_only: self.ttfb_only,
+				user_agents: user_agents.clone(),
+				user_agent_turns: user_agent_turns.clone(),
+				etags: self.etag.then(Mutex::default),
 				requests,
-				client: client.clone(),
+				stop: stop.clone(),
+				issued: issued.clone(),
+				rng: Mutex::new(StdRng::seed_from_u64(seeds.gen())),
+				reconnect: self.requests_per_conn.zip(self.client_hook.clone()).map(
+					|(requests, hook)| {
+						let local_address = local_address(index);
+						let new_client: NewClient = Arc::new(move || hook(local_address));
+						(requests, new_client)
+					},
+				),
+				client: if own_client {
+					Arc::new(new_client(index)?)
+				} else {
+					clients[index as usize % clients.len()].clone()
+				},
+				id: index,
 				sender: sender.clone(),
-				body: self.body,
 			};
+			// a single worker schedules every request of the open loop
+			if let Some(qps) = arrival {
+				let in_flight = Arc::new(Semaphore::new(self.workers as usize));
+				let interval = Duration::from_secs_f64(1_f64 / qps).max(Duration::from_nanos(1));
+				tokio::spawn(Arc::new(worker).execute_open_loop(interval, in_flight));
+				break;
+			}
+			let delay = self
+				.ramp_up
+				.map(|ramp_up| ramp_up * index as u32 / self.workers as u32)
+				.unwrap_or_default();
 			tokio::spawn(async move {
+				if !delay.is_zero() {
+					tokio::time::sleep(delay).await;
+					if worker.sender.is_closed() || worker.stop.load(Ordering::Relaxed) {
+						info!("run cancelled before the worker started");
+						return;
+					}
+				}
 				worker.execute().await;
 			});
 		}
@@ -129,33 +1187,280 @@ where
 
 		let mut total_requests = 0_u64;
 		let mut success_requests = 0_u64;
-		let mut durations = vec![];
-		let mut status_codes = vec![];
+		let mut responses = vec![];
+		let mut streaming = self.streaming.then(StreamingLatencies::default);
+		let mut status_code_dist = BTreeMap::new();
 		let mut size_total = 0_u64;
+		let mut dns_total = 0_f64;
+		let mut ttfb_total = 0_f64;
+		let mut retried_requests = 0_u64;
+		let mut handshake_requests = 0_u64;
+		let mut redirected_requests = 0_u64;
+		let mut redirected_total = 0_f64;
+		let mut slow_requests = 0_u64;
+		let mut throttled_requests = 0_u64;
+		let mut not_modified_requests = 0_u64;
+		// a response on a connection never seen before opened it, the others reused one
+		// unless their setup took over the reuse threshold.
+		// The first and last response on every connection, and how long those replaced lasted
+		let mut connections: HashMap<SocketAddr, (Instant, Instant)> = HashMap::new();
+		let mut replaced_connections: Vec<Duration> = vec![];
+		let mut reused_connections = 0_u64;
 		let mut error_dist = HashMap::new();
+		// first message of every error category, shown along with it
+		let mut error_messages: HashMap<&'static str, String> = HashMap::new();
+		let mut records = vec![];
+		let mut timeline: Vec<u64> = vec![];
+		let mut events = self.events;
+		let mut hdr_histogram = self
+			.hdr
+			.then(|| Histogram::<u64>::new(3).expect("3 significant figures are valid"));
+		// latencies since the last progress line, so it shows how they evolve
+		let mut live_latencies = self
+			.progress
+			.then(|| Histogram::<u64>::new(3).expect("3 significant figures are valid"));
+		let mut url_status_dist = BTreeMap::new();
+		let mut url_requests = BTreeMap::new();
+		let mut worker_dist = BTreeMap::new();
+		let mut warmup_requests = 0_u64;
+		let mut warmup_elapsed = Duration::ZERO;
 
+		let timer = self.duration.map(|duration| {
+			let cancel = cancel.clone();
+			tokio::spawn(async move {
+				tokio::time::sleep(duration).await;
+				cancel.notify_one();
+			})
+		});
+		let second = Duration::from_secs(1);
+		let mut ticker = tokio::time::interval_at(start + second, second);
+		let mut last_total = 0_u64;
+		let mut drain_deadline = None;
+		let mut max_errors_reached = None;
 		loop {
+			if self.max_requests.is_some_and(|max| total_requests >= max) {
+				info!("max requests reached, stopping the run");
+				stop.store(true, Ordering::Relaxed);
+				receiver.close();
+				break;
+			}
+			// results are only counted here, so the error count needs no synchronization
+			if max_errors_reached.is_none()
+				&& self.max_errors.is_some_and(|max| {
+					total_requests - success_requests - throttled_requests >= max
+				}) {
+				info!("max errors reached, cancelling the run");
+				max_errors_reached = self.max_errors;
+				cancel.notify_one();
+			}
 			tokio::select! {
-				_ = cancel.notified()=>{
-					info!("receive cancel signal");
+				_ = cancel.notified(), if drain_deadline.is_none() => {
+					stop.store(true, Ordering::Relaxed);
+					match self.drain_timeout {
+						Some(timeout) => {
+							info!("receive cancel signal, draining requests in flight");
+							drain_deadline = Some(Instant::now() + timeout);
+						}
+						None => {
+							info!("receive cancel signal");
+							receiver.close();
+							break;
+						}
+					}
+				}
+				_ = tokio::time::sleep_until(drain_deadline.unwrap_or(start)), if drain_deadline.is_some() => {
+					info!("drain timeout elapsed, dropping requests still in flight");
 					receiver.close();
 					break;
 				}
+				_ = ticker.tick(), if self.progress || events.is_some() => {
+					if let Some(latencies) = &mut live_latencies {
+						print_progress(&progress_line(
+							total_requests,
+							total_requests - last_total,
+							total_requests - success_requests - throttled_requests,
+							latencies,
+							start.elapsed(),
+						));
+						latencies.reset();
+						last_total = total_requests;
+					}
+					if let Some(Err(err)) = events.as_ref().map(|writer| writer.lock().unwrap().flush()) {
+						warn!("fail to write events, stop writing them: {}", err);
+						events = None;
+					}
+				}
 				msg = receiver.recv() =>{
 					match msg{
 						None => {
 							info!("all sender of worker been closed, finish receiving source stats");
 							break;
 						},
-						Some(result)=>{
+						Some((worker, index, result))=>{
+							let in_warmup = warmup_requests < self.warmup_requests
+								|| self.warmup.is_some_and(|warmup| start.elapsed() < warmup);
+							if in_warmup {
+								warmup_requests += 1;
+								warmup_elapsed = start.elapsed();
+								continue;
+							}
 							total_requests += 1;
+							if self.per_worker {
+								worker_dist.entry(worker).or_insert((0, 0, 0, 0_f64)).0 += 1;
+							}
+							if self.timeline {
+								let second = start.elapsed().as_secs() as usize;
+								if timeline.len() <= second {
+									timeline.resize(second + 1, 0);
+								}
+								timeline[second] += 1;
+							}
+							if self.targets.len() > 1 {
+								*url_requests
+									.entry(self.targets[index].url.to_string())
+									.or_insert(0) += 1;
+							}
+							if let Some(writer) = &events {
+								let t = start.elapsed().as_secs_f64();
+								let event = match &result {
+									Err(err) => Event {
+										t,
+										error: Some(err.to_string()),
+										..Event::default()
+									},
+									Ok(stat) => Event {
+										t,
+										duration: Some(stat.duration.as_secs_f64()),
+										status: Some(stat.status_code),
+										size: stat.size,
+										error: stat.failure.clone(),
+									},
+								};
+								let written = event.write_jsonl(&mut *writer.lock().unwrap());
+								if let Err(err) = written {
+									warn!("fail to write events, stop writing them: {}", err);
+									events = None;
+								}
+							}
+							if self.records {
+								records.push(match &result {
+									Err(err) => Record {
+										error: Some(err.to_string()),
+										..Record::default()
+									},
+									Ok(stat) => Record {
+										duration: Some(stat.duration.as_secs_f64()),
+										status_code: Some(stat.status_code),
+										size: stat.size,
+										error: stat.failure.clone(),
+									},
+								});
+							}
 							match result{
-								Err(err)=>*error_dist.entry(err.to_string()).or_insert(0) += 1,
+								Err(err)=>{
+									let key = if self.verbose_errors {
+										err.to_string()
+									} else {
+										let category = error_category(&err);
+										match err
+											.url()
+											.and_then(Url::host_str)
+											.filter(|_| category == "dns")
+										{
+											Some(host) => format!("dns: fail to resolve {}", host),
+											None => {
+												let message = error_messages
+													.entry(category)
+													.or_insert_with(|| err.to_string());
+												format!("{}: {}", category, message)
+											}
+										}
+									};
+									*error_dist.entry(key).or_insert(0) += 1;
+								}
 								Ok(stat)=>{
+									*status_code_dist.entry(stat.status_code).or_insert(0) += 1;
+									if let Some(addr) = stat.local_addr {
+										let now = Instant::now();
+										match connections.entry(addr) {
+											Entry::Occupied(mut entry) if stat.dns > self.reuse_threshold => {
+												let (first, last) = entry.insert((now, now));
+												replaced_connections.push(last - first);
+											}
+											Entry::Occupied(mut entry) => {
+												entry.get_mut().1 = now;
+												reused_connections += 1;
+											}
+											Entry::Vacant(entry) => {
+												entry.insert((now, now));
+											}
+										}
+									}
+									if self.targets.len() > 1 {
+										*url_status_dist
+											.entry(self.targets[index].url.to_string())
+											.or_insert_with(BTreeMap::new)
+											.entry(stat.status_code)
+											.or_insert(0) += 1;
+									}
+									if self
+										.throttle_status
+										.as_ref()
+										.is_some_and(|throttle| throttle.contains(stat.status_code))
+									{
+										throttled_requests += 1;
+										if self.per_worker {
+											worker_dist
+												.get_mut(&worker)
+												.expect("every result of the worker is counted")
+												.2 += 1;
+										}
+										continue;
+									}
+									if let Some(failure) = stat.failure {
+										*error_dist.entry(failure).or_insert(0) += 1;
+										continue;
+									}
 									success_requests += 1;
-									durations.push(stat.duration.as_secs_f64());
-									status_codes.push(stat.status_code);
-									size_total += stat.content_length;
+									if self.per_worker {
+										let (_, success, _, total) = worker_dist
+											.get_mut(&worker)
+											.expect("every result of the worker is counted");
+										*success += 1;
+										*total += stat.duration.as_secs_f64();
+									}
+									match &mut streaming {
+										Some(latencies) => latencies
+											.record(stat.status_code, stat.duration.as_secs_f64()),
+										None => responses
+											.push((stat.status_code, stat.duration.as_secs_f64())),
+									}
+									// the histograms grow instead, saturating would clamp to their initial range
+									for histogram in [&mut hdr_histogram, &mut live_latencies]
+										.into_iter()
+										.flatten()
+									{
+										let _ = histogram.record(stat.duration.as_micros() as u64);
+									}
+									size_total += stat.size;
+									dns_total += stat.dns.as_secs_f64();
+									ttfb_total += stat.ttfb.as_secs_f64();
+									if stat.retries > 0 {
+										retried_requests += 1;
+									}
+									if stat.handshake {
+										handshake_requests += 1;
+									}
+									if stat.redirected {
+										redirected_requests += 1;
+										redirected_total += stat.duration.as_secs_f64();
+									}
+									if stat.status_code == 304 {
+										not_modified_requests += 1;
+									}
+									if self.slow_threshold.is_some_and(|threshold| stat.duration > threshold) {
+										slow_requests += 1;
+									}
 								}
 							}
 						}
@@ -164,13 +1469,1102 @@ where
 			}
 		}
 
+		if let Some(timer) = timer {
+			timer.abort();
+		}
+		if let Some(Err(err)) = events.as_ref().map(|writer| writer.lock().unwrap().flush()) {
+			warn!("fail to write events: {}", err);
+		}
+		if self.progress {
+			clear_progress();
+		}
+		let families = (
+			connections.keys().any(SocketAddr::is_ipv4),
+			connections.keys().any(SocketAddr::is_ipv6),
+		);
+		let address_family = match families {
+			(true, true) => Some("IPv4 and IPv6".to_string()),
+			(true, false) => Some(IpFamily::V4.to_string()),
+			(false, true) => Some(IpFamily::V6.to_string()),
+			(false, false) => None,
+		};
+		// requests still running or whose result wasn't received when the run was cut off
+		let in_flight_requests = issued
+			.load(Ordering::Relaxed)
+			.saturating_sub(total_requests + warmup_requests);
 		Ok(Reporter {
 			total_requests,
 			success_requests,
-			durations,
-			status_codes,
+			responses,
+			status_code_dist,
 			size_total,
+			dns_total,
+			ttfb_total,
+			retried_requests,
+			handshake_requests,
+			redirected_requests,
+			redirected_total,
+			slow_requests,
+			slow_threshold: self.slow_threshold,
+			throttled_requests,
+			not_modified_requests,
+			body_dist: body_dir.map(|body_dir| body_dir.sent()).unwrap_or_default(),
+			user_agents: user_agent_turns
+				.load(Ordering::Relaxed)
+				.min(user_agents.len() as u64),
+			new_connections: (connections.len() + replaced_connections.len()) as u64,
+			connection_lifetime: match connections.len() + replaced_connections.len() {
+				0 => 0_f64,
+				len => {
+					connections
+						.values()
+						.map(|(first, last)| *last - *first)
+						.chain(replaced_connections)
+						.map(|lifetime| lifetime.as_secs_f64())
+						.sum::<f64>() / len as f64
+				}
+			},
+			address_family,
+			reused_connections,
+			warmup_requests,
+			warmup_elapsed,
 			error_dist,
+			url_status_dist,
+			url_requests,
+			worker_dist,
+			records,
+			timeline,
+			max_errors_reached,
+			hdr_histogram,
+			streaming,
+			in_flight_requests,
+			..Reporter::default()
 		})
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use std::net::{IpAddr, SocketAddr};
+	use std::sync::atomic::{AtomicBool, AtomicU64};
+	use std::sync::{Arc, Mutex};
+	use std::time::Duration;
+
+	use bytes::Bytes;
+	use futures::TryStreamExt;
+	use hdrhistogram::Histogram;
+	use http::{HeaderMap, HeaderValue, Method};
+	use rand::rngs::StdRng;
+	use rand::SeedableRng;
+	use regex::Regex;
+	use reqwest::{Client, Url};
+	use tokio::io::{AsyncReadExt, AsyncWriteExt};
+	use tokio::net::TcpListener;
+	use tokio::sync::mpsc::channel;
+
+	use crate::arg::parse_status_set;
+	use crate::body_dir::BodyDir;
+	use crate::client::{ClientBuilder, IpFamily};
+	use crate::compress::Compression;
+	use crate::work::{
+		cache_bust, error_category, file_stream, pick_weighted, progress_line, requests_of_worker,
+		think_time, BasicAuth, Expect, Retry, Target, WorkBuilder, Worker,
+	};
+
+	/// Serve the raw `response` to every connection, returns the url to request.
+	async fn serve<R: AsRef<[u8]> + Send + Sync + 'static>(response: R) -> Url {
+		serve_after(response, Duration::ZERO).await
+	}
+
+	/// Serve the raw `response` `delay` after every request.
+	async fn serve_after<R: AsRef<[u8]> + Send + Sync + 'static>(
+		response: R,
+		delay: Duration,
+	) -> Url {
+		let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+		let addr = listener.local_addr().unwrap();
+		let response = Arc::new(response);
+		tokio::spawn(async move {
+			loop {
+				let (mut stream, _) = listener.accept().await.unwrap();
+				let response = response.clone();
+				tokio::spawn(async move {
+					let mut buf = [0_u8; 4096];
+					while let Ok(n) = stream.read(&mut buf).await {
+						tokio::time::sleep(delay).await;
+						if n == 0 || stream.write_all((*response).as_ref()).await.is_err() {
+							return;
+						}
+					}
+				});
+			}
+		});
+		format!("http://{}/", addr).parse().unwrap()
+	}
+
+	/// Serve the raw response `respond` gives for every request and the address
+	/// it came from. The connection is closed after an empty response or one
+	/// with Connection: close.
+	async fn serve_fn<F>(respond: F) -> Url
+	where
+		F: Fn(&str, SocketAddr) -> &'static str + Send + Sync + 'static,
+	{
+		let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+		let addr = listener.local_addr().unwrap();
+		let respond = Arc::new(respond);
+		tokio::spawn(async move {
+			loop {
+				let (mut stream, peer) = listener.accept().await.unwrap();
+				let respond = respond.clone();
+				tokio::spawn(async move {
+					let mut buf = [0_u8; 4096];
+					while let Ok(n) = stream.read(&mut buf).await {
+						if n == 0 {
+							return;
+						}
+						let response = respond(&String::from_utf8_lossy(&buf[..n]), peer);
+						let close = response.is_empty()
+							|| response
+								.to_ascii_lowercase()
+								.contains("\r\nconnection: close\r\n");
+						if stream.write_all(response.as_bytes()).await.is_err() || close {
+							return;
+						}
+					}
+				});
+			}
+		});
+		format!("http://{}/", addr).parse().unwrap()
+	}
+
+	fn target(url: Url) -> Target<&'static [u8]> {
+		Target {
+			method: Method::GET,
+			url,
+			headers: HeaderMap::new(),
+			body: b"",
+		}
+	}
+
+	fn worker(url: Url) -> Worker<&'static [u8]> {
+		let (sender, _) = channel(1);
+		Worker {
+			targets: vec![target(url)],
+			template: None,
+			multipart: None,
+			body_file: None,
+			body_dir: None,
+			weights: None,
+			offset: 0,
+			basic_auth: None,
+			bearer: None,
+			version: None,
+			cache_bust: None,
+			rate_limit: None,
+			correct_co: false,
+			limiter: None,
+			connections: None,
+			think: None,
+			think_jitter: Duration::ZERO,
+			retry: Retry::default(),
+			expect: Arc::new(Expect::default()),
+			skip_body: false,
+			ttfb_only: false,
+			user_agents: Arc::new(vec![]),
+			user_agent_turns: Arc::new(AtomicU64::new(0)),
+			reconnect: None,
+			etags: None,
+			requests: Some(1),
+			stop: Arc::new(AtomicBool::new(false)),
+			issued: Arc::new(AtomicU64::new(0)),
+			rng: Mutex::new(StdRng::seed_from_u64(0)),
+			client: Arc::new(Client::new()),
+			id: 0,
+			sender,
+		}
+	}
+
+	#[test]
+	fn think_time_should_stay_in_jitter() {
+		let mut rng = StdRng::seed_from_u64(0);
+		let (base, jitter) = (Duration::from_millis(100), Duration::from_millis(20));
+		for _ in 0..100 {
+			let pause = think_time(base, jitter, &mut rng);
+			assert!(pause >= base - jitter && pause <= base + jitter);
+		}
+		assert_eq!(think_time(base, Duration::ZERO, &mut rng), base);
+		let pause = think_time(Duration::ZERO, jitter, &mut rng);
+		assert!(pause <= jitter);
+	}
+
+	#[test]
+	fn cache_bust_should_keep_existing_query() {
+		let mut rng = StdRng::seed_from_u64(0);
+		let mut url: Url = "http://localhost/a".parse().unwrap();
+		cache_bust(&mut url, "_", &mut rng);
+		let pairs: Vec<_> = url.query_pairs().into_owned().collect();
+		assert_eq!(pairs.len(), 1);
+		assert_eq!((pairs[0].0.as_str(), pairs[0].1.len()), ("_", 16));
+
+		let mut url: Url = "http://localhost/a?q=1&cb=x".parse().unwrap();
+		cache_bust(&mut url, "cb", &mut rng);
+		let pairs: Vec<_> = url.query_pairs().into_owned().collect();
+		assert_eq!(
+			pairs[..2],
+			[
+				("q".to_string(), "1".to_string()),
+				("cb".to_string(), "x".to_string())
+			]
+		);
+		assert_eq!(pairs[2].0, "cb");
+		assert_ne!(pairs[2].1, "x");
+	}
+
+	#[test]
+	fn pick_weighted_should_follow_weights() {
+		let mut rng = StdRng::seed_from_u64(0);
+		let cumulative = [7_u64, 10];
+		let mut counts = [0_u32; 2];
+		for _ in 0..10000 {
+			counts[pick_weighted(&cumulative, &mut rng)] += 1;
+		}
+		assert!((6800..7200).contains(&counts[0]));
+		assert_eq!(pick_weighted(&[0, 1], &mut rng), 1);
+	}
+
+	#[tokio::test]
+	async fn make_request_should_measure_chunked_body() {
+		let url = serve(
+			"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhello\r\n6\r\n world\r\n0\r\n\r\n",
+		)
+		.await;
+		let stat = worker(url.clone())
+			.make_request(&target(url))
+			.await
+			.unwrap();
+		assert_eq!(stat.status_code, 200);
+		assert_eq!(stat.size, 11);
+	}
+
+	#[tokio::test]
+	async fn file_stream_should_read_whole_file() {
+		let path = std::env::temp_dir().join("rey-stream-body-test.bin");
+		let content: Vec<u8> = (0..200_000_u32).map(|i| i as u8).collect();
+		std::fs::write(&path, &content).unwrap();
+		let chunks: Vec<Bytes> = file_stream(Arc::new(path.clone()))
+			.try_collect()
+			.await
+			.unwrap();
+		std::fs::remove_file(&path).unwrap();
+		assert!(chunks.len() > 1);
+		assert_eq!(chunks.concat(), content);
+		let missing = file_stream(Arc::new(path))
+			.try_collect::<Vec<Bytes>>()
+			.await;
+		assert!(missing.is_err());
+	}
+
+	#[tokio::test]
+	async fn execute_should_count_reused_connections() {
+		let url = serve("HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n").await;
+		for (disable_keepalive, new, reused) in [(false, 2, 8), (true, 10, 0)] {
+			let work = WorkBuilder::new()
+				.client(ClientBuilder {
+					disable_keepalive,
+					..ClientBuilder::default()
+				})
+				.url(url.clone())
+				.workers(2)
+				.total_requests(10)
+				.build()
+				.unwrap();
+			let reporter = crate::run(work).await.unwrap();
+			assert_eq!(
+				(reporter.new_connections, reporter.reused_connections),
+				(new, reused)
+			);
+		}
+	}
+
+	#[tokio::test]
+	async fn execute_should_not_time_dns_of_reused_connections() {
+		let mut url = serve("HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n").await;
+		url.set_host(Some("localhost")).unwrap();
+		// only new connections look the name up, pooled ones stay within a threshold of zero
+		let work = WorkBuilder::new()
+			.client(ClientBuilder {
+				ip_family: Some(IpFamily::V4),
+				..ClientBuilder::default()
+			})
+			.url(url)
+			.workers(2)
+			.total_requests(10)
+			.reuse_threshold(Duration::ZERO)
+			.build()
+			.unwrap();
+		let reporter = crate::run(work).await.unwrap();
+		assert_eq!(
+			(reporter.new_connections, reporter.reused_connections),
+			(2, 8)
+		);
+	}
+
+	#[tokio::test]
+	async fn execute_should_close_connections_after_requests_per_conn() {
+		let url = serve("HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n").await;
+		let work = WorkBuilder::new()
+			.url(url)
+			.workers(2)
+			.total_requests(12)
+			.requests_per_conn(3)
+			.build()
+			.unwrap();
+		let reporter = crate::run(work).await.unwrap();
+		assert_eq!(reporter.success_requests, 12);
+		assert_eq!(
+			(reporter.new_connections, reporter.reused_connections),
+			(4, 8)
+		);
+		assert!(reporter.connection_lifetime > 0_f64);
+	}
+
+	#[test]
+	fn progress_line_should_show_recent_percentiles() {
+		let mut latencies = Histogram::<u64>::new(3).unwrap();
+		let line = progress_line(10, 5, 1, &latencies, Duration::from_millis(2500));
+		assert_eq!(line, "10 requests, 5 req/s, 1 errors, 2s elapsed");
+		for micros in 1..=100 {
+			latencies.record(micros * 1_000).unwrap();
+		}
+		let line = progress_line(110, 100, 1, &latencies, Duration::from_secs(3));
+		assert_eq!(
+			line,
+			"110 requests, 100 req/s, 1 errors, p50 0.0500 secs, p99 0.0990 secs, 3s elapsed"
+		);
+	}
+
+	#[test]
+	fn requests_of_worker_should_sum_to_total() {
+		for (total, workers) in [(205_u64, 50_u16), (200, 50), (7, 3), (1, 4), (1001, 10)] {
+			let sum: u64 = (0..workers)
+				.map(|index| requests_of_worker(total, workers, index))
+				.sum();
+			assert_eq!(sum, total);
+		}
+	}
+
+	#[test]
+	fn requests_of_worker_should_give_remainder_to_first_workers() {
+		assert_eq!(requests_of_worker(205, 50, 0), 5);
+		assert_eq!(requests_of_worker(205, 50, 4), 5);
+		assert_eq!(requests_of_worker(205, 50, 5), 4);
+		assert_eq!(requests_of_worker(205, 50, 49), 4);
+	}
+
+	#[tokio::test]
+	async fn make_request_with_retry_should_retry_5xx() {
+		let url = serve("HTTP/1.1 503 Service Unavailable\r\nContent-Length: 0\r\n\r\n").await;
+		let mut worker = worker(url.clone());
+		let stat = worker
+			.make_request_with_retry(&target(url.clone()))
+			.await
+			.unwrap();
+		assert_eq!(stat.retries, 0);
+		worker.retry = Retry {
+			retries: 2,
+			backoff: Duration::from_millis(1),
+			on_5xx: true,
+		};
+		let stat = worker.make_request_with_retry(&target(url)).await.unwrap();
+		assert_eq!(stat.status_code, 503);
+		assert_eq!(stat.retries, 2);
+	}
+
+	#[test]
+	fn backoff_of_should_saturate() {
+		let retry = Retry {
+			retries: u32::MAX,
+			backoff: Duration::from_millis(10),
+			on_5xx: false,
+		};
+		assert_eq!(retry.backoff_of(0), Duration::from_millis(10));
+		assert_eq!(retry.backoff_of(3), Duration::from_millis(80));
+		assert_eq!(retry.backoff_of(32), Duration::from_millis(10) * u32::MAX);
+		let retry = Retry {
+			backoff: Duration::from_secs(u64::MAX / 2 + 1),
+			..retry
+		};
+		assert_eq!(retry.backoff_of(1), Duration::MAX);
+	}
+
+	#[tokio::test]
+	async fn make_request_should_check_expectations() {
+		let url = serve("HTTP/1.1 200 OK\r\nContent-Length: 11\r\n\r\nhello world").await;
+		let mut worker = worker(url.clone());
+		let stat = worker.make_request(&target(url.clone())).await.unwrap();
+		assert_eq!(stat.failure, None);
+		worker.expect = Arc::new(Expect {
+			body: Some("world".to_string()),
+			body_regex: Some(Regex::new("^hel+o").unwrap()),
+			..Expect::default()
+		});
+		let stat = worker.make_request(&target(url.clone())).await.unwrap();
+		assert_eq!(stat.failure, None);
+		worker.expect = Arc::new(Expect {
+			body: Some("bye".to_string()),
+			..Expect::default()
+		});
+		let stat = worker.make_request(&target(url.clone())).await.unwrap();
+		assert_eq!(
+			stat.failure.unwrap(),
+			"assertion failed: body doesn't contain \"bye\""
+		);
+		worker.expect = Arc::new(Expect {
+			status: Some(parse_status_set("201,3xx").unwrap()),
+			body_regex: Some(Regex::new("^world").unwrap()),
+			..Expect::default()
+		});
+		let stat = worker.make_request(&target(url)).await.unwrap();
+		assert_eq!(stat.failure.unwrap(), "unexpected status 200");
+	}
+
+	#[tokio::test]
+	async fn make_request_should_answer_digest_challenge() {
+		let url = serve(
+			"HTTP/1.1 401 Unauthorized\r\nWWW-Authenticate: Digest realm=\"rey\", nonce=\"abc\"\r\nContent-Length: 0\r\n\r\n",
+		)
+		.await;
+		let mut worker = worker(url.clone());
+		let stat = worker.make_request(&target(url.clone())).await.unwrap();
+		assert!(!stat.handshake);
+		worker.basic_auth = Some(BasicAuth {
+			username: "root".to_string(),
+			password: None,
+			digest: true,
+		});
+		let stat = worker.make_request(&target(url)).await.unwrap();
+		assert!(stat.handshake);
+		assert_eq!(stat.status_code, 401);
+	}
+
+	#[tokio::test]
+	async fn error_category_should_name_connect_and_timeout() {
+		let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+		let url: Url = format!("http://{}/", listener.local_addr().unwrap())
+			.parse()
+			.unwrap();
+		drop(listener);
+		let err = Client::new().get(url).send().await.unwrap_err();
+		assert_eq!(error_category(&err), "connect");
+
+		let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+		let url: Url = format!("http://{}/", listener.local_addr().unwrap())
+			.parse()
+			.unwrap();
+		let err = Client::new()
+			.get(url)
+			.timeout(Duration::from_millis(50))
+			.send()
+			.await
+			.unwrap_err();
+		assert_eq!(error_category(&err), "timeout");
+		drop(listener);
+	}
+
+	#[tokio::test]
+	async fn execute_should_count_every_worker() {
+		let url = serve("HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n").await;
+		let work = WorkBuilder::new()
+			.url(url)
+			.workers(3)
+			.total_requests(10)
+			.per_worker(true)
+			.build()
+			.unwrap();
+		let reporter = crate::run(work).await.unwrap();
+		let requests: Vec<(u16, u64, u64)> = reporter
+			.worker_dist
+			.into_iter()
+			.map(|(worker, (requests, success, _, _))| (worker, requests, success))
+			.collect();
+		assert_eq!(requests, vec![(0, 4, 4), (1, 3, 3), (2, 3, 3)]);
+	}
+
+	#[tokio::test]
+	async fn execute_should_connect_to_encoded_idn_host() {
+		let url = serve("HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n").await;
+		let addr = url.socket_addrs(|| None).unwrap()[0];
+		// only the punycode name resolves to the server
+		let work = WorkBuilder::new()
+			.client(ClientBuilder {
+				resolve: vec![("xn--r8jz45g.xn--zckzah".to_string(), addr)],
+				..ClientBuilder::default()
+			})
+			.url(
+				format!("http://例え.テスト:{}/", addr.port())
+					.parse()
+					.unwrap(),
+			)
+			.workers(1)
+			.total_requests(1)
+			.build()
+			.unwrap();
+		let reporter = crate::run(work).await.unwrap();
+		assert_eq!(reporter.success_requests, 1);
+	}
+
+	#[tokio::test]
+	async fn execute_should_count_throttled_responses_apart() {
+		let url = serve("HTTP/1.1 429 Too Many Requests\r\nContent-Length: 0\r\n\r\n").await;
+		let work = WorkBuilder::new()
+			.url(url)
+			.workers(2)
+			.total_requests(4)
+			.expect(Expect {
+				status: Some(parse_status_set("2xx").unwrap()),
+				..Expect::default()
+			})
+			.throttle_status(parse_status_set("429").unwrap())
+			.build()
+			.unwrap();
+		let reporter = crate::run(work).await.unwrap();
+		assert_eq!(
+			(reporter.throttled_requests, reporter.success_requests),
+			(4, 0)
+		);
+		assert!(reporter.error_dist.is_empty());
+		assert!(reporter.responses.is_empty());
+		assert_eq!(reporter.status_code_dist[&429], 4);
+		let report = reporter.into_report(Duration::from_secs(1), &[50_f64]);
+		assert_eq!((report.success_rate, report.error_rate), (0_f64, 0_f64));
+		assert!(report.render().contains("Throttled responses:  4"));
+	}
+
+	#[tokio::test]
+	async fn execute_should_run_copies_of_the_work_afresh() {
+		let url = serve("HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n").await;
+		let work = WorkBuilder::new()
+			.url(url)
+			.workers(2)
+			.total_requests(6)
+			.build()
+			.unwrap();
+		let first = crate::run(work.clone()).await.unwrap();
+		let second = crate::run(work).await.unwrap();
+		for reporter in [first, second] {
+			assert_eq!(
+				(reporter.success_requests, reporter.new_connections),
+				(6, 2)
+			);
+		}
+	}
+
+	#[tokio::test]
+	async fn execute_should_count_body_files_sent() {
+		let url = serve("HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n").await;
+		let dir = std::env::temp_dir().join("rey-work-body-dir-test");
+		std::fs::create_dir_all(&dir).unwrap();
+		std::fs::write(dir.join("a.json"), "{}").unwrap();
+		std::fs::write(dir.join("b.json"), "[]").unwrap();
+		let body_dir = BodyDir::load(&dir).unwrap();
+		std::fs::remove_dir_all(&dir).unwrap();
+		let work = WorkBuilder::new()
+			.url(url)
+			.method(Method::POST)
+			.body_dir(body_dir)
+			.workers(2)
+			.total_requests(20)
+			.build()
+			.unwrap();
+		let reporter = crate::run(work).await.unwrap();
+		assert_eq!(reporter.success_requests, 20);
+		assert_eq!(
+			reporter.body_dist.keys().collect::<Vec<_>>(),
+			["a.json", "b.json"]
+		);
+		assert_eq!(reporter.body_dist.values().sum::<u64>(), 20);
+	}
+
+	#[tokio::test]
+	async fn execute_should_send_etag_back() {
+		let url = serve_fn(|request, _| {
+			if request
+				.to_ascii_lowercase()
+				.contains("if-none-match: \"v1\"")
+			{
+				"HTTP/1.1 304 Not Modified\r\nETag: \"v1\"\r\n\r\n"
+			} else {
+				"HTTP/1.1 200 OK\r\nETag: \"v1\"\r\nContent-Length: 2\r\n\r\nok"
+			}
+		})
+		.await;
+		// a cache-busted url still gets the ETag of its target
+		for (etag, cache_bust, not_modified) in
+			[(false, false, 0), (true, false, 4), (true, true, 4)]
+		{
+			let work = WorkBuilder::new()
+				.url(url.clone())
+				.workers(1)
+				.total_requests(5)
+				.etag(etag)
+				.cache_bust(cache_bust.then(|| "cb".to_string()))
+				.build()
+				.unwrap();
+			let reporter = crate::run(work).await.unwrap();
+			assert_eq!(reporter.not_modified_requests, not_modified);
+			assert_eq!(reporter.success_requests, 5);
+		}
+	}
+
+	#[tokio::test]
+	async fn execute_should_rotate_user_agents() {
+		let url = serve_fn(|request, _| {
+			let request = request.to_ascii_lowercase();
+			if ["user-agent: a\r\n", "user-agent: b\r\n"]
+				.iter()
+				.any(|header| request.contains(header))
+			{
+				"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n"
+			} else {
+				"HTTP/1.1 500 Internal Server Error\r\nContent-Length: 0\r\n\r\n"
+			}
+		})
+		.await;
+		let work = WorkBuilder::new()
+			.url(url)
+			.user_agents(vec![
+				HeaderValue::from_static("a"),
+				HeaderValue::from_static("b"),
+			])
+			.workers(2)
+			.total_requests(6)
+			.build()
+			.unwrap();
+		let reporter = crate::run(work).await.unwrap();
+		assert_eq!((reporter.success_requests, reporter.user_agents), (6, 2));
+	}
+
+	#[tokio::test]
+	async fn execute_should_bucket_dropped_connections() {
+		// closed after the headers before the promised body, and before any response
+		for response in [
+			"HTTP/1.1 200 OK\r\nContent-Length: 10\r\nConnection: close\r\n\r\nok",
+			"",
+		] {
+			let url = serve_fn(move |_, _| response).await;
+			let work = WorkBuilder::new()
+				.url(url)
+				.workers(1)
+				.total_requests(3)
+				.build()
+				.unwrap();
+			let reporter = crate::run(work).await.unwrap();
+			assert_eq!(reporter.error_dist.len(), 1);
+			let (key, count) = reporter.error_dist.iter().next().unwrap();
+			assert!(key.starts_with("conn-reset: "), "{}", key);
+			assert_eq!(*count, 3);
+		}
+	}
+
+	#[tokio::test]
+	async fn execute_should_bucket_dns_errors_by_host() {
+		let work = WorkBuilder::new()
+			.url("http://nonexistent.invalid/".parse().unwrap())
+			.workers(1)
+			.total_requests(2)
+			.build()
+			.unwrap();
+		let reporter = crate::run(work).await.unwrap();
+		assert_eq!(
+			reporter.error_dist,
+			[("dns: fail to resolve nonexistent.invalid".to_string(), 2)]
+				.into_iter()
+				.collect()
+		);
+	}
+
+	#[tokio::test]
+	async fn make_request_should_measure_wire_size() {
+		let body = Compression::Gzip.compress(b"hello world").unwrap();
+		let mut response = format!(
+			"HTTP/1.1 200 OK\r\nContent-Encoding: gzip\r\nContent-Length: {}\r\n\r\n",
+			body.len()
+		)
+		.into_bytes();
+		response.extend(&body);
+		let url = serve(response).await;
+		let mut worker = worker(url.clone());
+		worker.expect = Arc::new(Expect {
+			body: Some("hello".to_string()),
+			..Expect::default()
+		});
+		let stat = worker.make_request(&target(url.clone())).await.unwrap();
+		assert_eq!((stat.size, stat.failure), (11, None));
+		worker.client = Arc::new(Client::builder().no_gzip().build().unwrap());
+		let stat = worker.make_request(&target(url)).await.unwrap();
+		assert_eq!((stat.size, stat.failure), (body.len() as u64, None));
+	}
+
+	#[tokio::test]
+	async fn make_request_should_not_read_head_body() {
+		let url = serve("HTTP/1.1 200 OK\r\nContent-Length: 11\r\n\r\n").await;
+		let mut worker = worker(url.clone());
+		let head = Target {
+			method: Method::HEAD,
+			..target(url.clone())
+		};
+		let stat = worker.make_request(&head).await.unwrap();
+		assert_eq!((stat.status_code, stat.size), (200, 0));
+
+		let url = serve("HTTP/1.1 200 OK\r\nContent-Length: 11\r\n\r\nhello world").await;
+		worker.skip_body = true;
+		let stat = worker.make_request(&target(url.clone())).await.unwrap();
+		assert_eq!((stat.status_code, stat.size), (200, 11));
+		worker.ttfb_only = true;
+		let stat = worker.make_request(&target(url)).await.unwrap();
+		assert_eq!((stat.status_code, stat.size), (200, 0));
+		assert_eq!(stat.duration, stat.ttfb);
+	}
+
+	#[tokio::test]
+	async fn execute_should_only_wait_for_a_full_result_buffer() {
+		let url = serve("HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n").await;
+		for (buffer, finished) in [(1, false), (5, true)] {
+			let (sender, _receiver) = channel(buffer);
+			let worker = Worker {
+				requests: Some(5),
+				sender,
+				..worker(url.clone())
+			};
+			// nothing is received, so the worker only finishes if every result fits
+			let result = tokio::time::timeout(Duration::from_millis(500), worker.execute()).await;
+			assert_eq!(result.is_ok(), finished);
+		}
+	}
+
+	#[tokio::test(start_paused = true)]
+	async fn execute_should_keep_low_rates() {
+		// a closed port fails at once, so only the rate limit paces the worker
+		let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+		let url: Url = format!("http://{}/", listener.local_addr().unwrap())
+			.parse()
+			.unwrap();
+		drop(listener);
+		for (qps, expected) in [(0.1, 10_u32), (0.5, 50), (0.3, 30)] {
+			let (sender, mut receiver) = channel(100);
+			let worker = Worker {
+				rate_limit: Some(qps),
+				requests: None,
+				sender,
+				..worker(url.clone())
+			};
+			let window = Duration::from_secs(100) + Duration::from_millis(1);
+			let _ = tokio::time::timeout(window, worker.execute()).await;
+			let mut sent = 0_u32;
+			while receiver.try_recv().is_ok() {
+				sent += 1;
+			}
+			assert!(
+				sent.abs_diff(expected) <= 1,
+				"{} requests at {} qps",
+				sent,
+				qps
+			);
+		}
+	}
+
+	#[tokio::test]
+	async fn execute_should_correct_coordinated_omission() {
+		// every response stalls a worker due every 10ms for 50ms
+		let url = serve_after(
+			"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n",
+			Duration::from_millis(50),
+		)
+		.await;
+		for (correct_co, last_latency) in [(false, 0.05..0.09), (true, 0.2..0.3)] {
+			let (sender, mut receiver) = channel(5);
+			let worker = Worker {
+				rate_limit: Some(100_f64),
+				correct_co,
+				requests: Some(5),
+				sender,
+				..worker(url.clone())
+			};
+			worker.execute().await;
+			let mut latencies = vec![];
+			while let Ok((_, _, result)) = receiver.try_recv() {
+				latencies.push(result.unwrap().duration.as_secs_f64());
+			}
+			// the last request was due 40ms after the first, sent 200ms after it
+			let last = latencies[4];
+			assert!(last_latency.contains(&last), "{:?}", latencies);
+		}
+	}
+
+	#[tokio::test]
+	async fn execute_should_cap_connections() {
+		let url = serve_after(
+			"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n",
+			Duration::from_millis(50),
+		)
+		.await;
+		let work = WorkBuilder::new()
+			.client(ClientBuilder {
+				max_connections: Some(1),
+				..ClientBuilder::default()
+			})
+			.url(url)
+			.workers(4)
+			.total_requests(4)
+			.max_connections(1)
+			.build()
+			.unwrap();
+		let start = std::time::Instant::now();
+		let reporter = crate::run(work).await.unwrap();
+		// the 4 workers took turns on one connection
+		assert!(start.elapsed() >= Duration::from_millis(200));
+		assert_eq!(
+			(reporter.new_connections, reporter.reused_connections),
+			(1, 3)
+		);
+	}
+
+	/// A writer the test keeps reading after the work took it
+	#[derive(Clone, Default)]
+	struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+	impl std::io::Write for SharedBuffer {
+		fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+			self.0.lock().unwrap().extend_from_slice(buf);
+			Ok(buf.len())
+		}
+
+		fn flush(&mut self) -> std::io::Result<()> {
+			Ok(())
+		}
+	}
+
+	#[tokio::test]
+	async fn execute_should_stream_events() {
+		let url = serve("HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok").await;
+		let buffer = SharedBuffer::default();
+		let work = WorkBuilder::new()
+			.url(url)
+			.workers(2)
+			.total_requests(3)
+			.events(Arc::new(Mutex::new(buffer.clone())) as Arc<Mutex<dyn std::io::Write + Send>>)
+			.build()
+			.unwrap();
+		crate::run(work).await.unwrap();
+		let events = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+		let events: Vec<serde_json::Value> = events
+			.lines()
+			.map(|line| serde_json::from_str(line).unwrap())
+			.collect();
+		assert_eq!(events.len(), 3);
+		for event in events {
+			assert_eq!((&event["status"], &event["size"]), (&200.into(), &2.into()));
+			assert!(event["t"].as_f64().unwrap() >= event["duration"].as_f64().unwrap());
+			assert!(event["error"].is_null());
+		}
+	}
+
+	#[tokio::test]
+	async fn execute_should_connect_over_the_family() {
+		let mut url = serve("HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n").await;
+		url.set_host(Some("localhost")).unwrap();
+		let work = WorkBuilder::new()
+			.client(ClientBuilder {
+				ip_family: Some(IpFamily::V4),
+				..ClientBuilder::default()
+			})
+			.url(url)
+			.workers(1)
+			.total_requests(2)
+			.build()
+			.unwrap();
+		let reporter = crate::run(work).await.unwrap();
+		assert_eq!(reporter.success_requests, 2);
+		assert_eq!(reporter.address_family.as_deref(), Some("IPv4"));
+	}
+
+	#[cfg(target_os = "linux")]
+	#[tokio::test]
+	async fn execute_should_spread_local_addresses() {
+		let peers = Arc::new(Mutex::new(vec![]));
+		let requested = peers.clone();
+		let url = serve_fn(move |_, peer| {
+			requested.lock().unwrap().push(peer.ip());
+			"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n"
+		})
+		.await;
+		let addrs: Vec<IpAddr> = vec!["127.0.0.1".parse().unwrap(), "127.0.0.2".parse().unwrap()];
+		let work = WorkBuilder::new()
+			.url(url)
+			.workers(4)
+			.total_requests(8)
+			.local_addresses(addrs.clone())
+			.build()
+			.unwrap();
+		let reporter = crate::run(work).await.unwrap();
+		assert_eq!(reporter.success_requests, 8);
+		let mut peers = peers.lock().unwrap().clone();
+		peers.sort();
+		peers.dedup();
+		assert_eq!(peers, addrs);
+	}
+
+	#[tokio::test]
+	async fn execute_should_count_slow_responses() {
+		let url = serve_after(
+			"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n",
+			Duration::from_millis(20),
+		)
+		.await;
+		for (threshold, slow) in [(Duration::from_millis(10), 4), (Duration::from_secs(1), 0)] {
+			let work = WorkBuilder::new()
+				.url(url.clone())
+				.workers(2)
+				.total_requests(4)
+				.slow_threshold(threshold)
+				.build()
+				.unwrap();
+			let reporter = crate::run(work).await.unwrap();
+			assert_eq!(reporter.slow_requests, slow);
+			// slow responses are neither errors nor left out of the latencies
+			assert_eq!(reporter.success_requests, 4);
+			assert_eq!(reporter.responses.len(), 4);
+		}
+	}
+
+	#[tokio::test]
+	async fn execute_should_run_for_the_duration() {
+		let url = serve("HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n").await;
+		let work = WorkBuilder::new()
+			.url(url)
+			.workers(2)
+			.total_requests(10)
+			.duration(Duration::from_millis(300))
+			.build()
+			.unwrap();
+		let reporter = crate::run(work).await.unwrap();
+		assert!(reporter.total_requests > 100, "{}", reporter.total_requests);
+		assert_eq!(reporter.success_requests, reporter.total_requests);
+	}
+
+	#[tokio::test]
+	async fn execute_should_count_open_loop_latency_from_schedule() {
+		let url = serve_after(
+			"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n",
+			Duration::from_millis(50),
+		)
+		.await;
+		let work = WorkBuilder::new()
+			.url(url)
+			.workers(1)
+			.total_requests(5)
+			.global_rate_limit(100_f64)
+			.open_loop(true)
+			.build()
+			.unwrap();
+		let reporter = crate::run(work).await.unwrap();
+		assert_eq!(reporter.success_requests, 5);
+		// a request every 10ms but one at a time, the last one waited for the 4 before it
+		let slowest = reporter
+			.responses
+			.iter()
+			.map(|(_, latency)| *latency)
+			.fold(0_f64, f64::max);
+		assert!(slowest > 0.2, "{}", slowest);
+
+		let work = WorkBuilder::new()
+			.url("http://localhost/".parse().unwrap())
+			.workers(1)
+			.open_loop(true)
+			.build()
+			.unwrap();
+		assert!(crate::run(work).await.is_err());
+	}
+
+	#[test]
+	fn build_should_validate_work() {
+		let url: Url = "http://localhost/".parse().unwrap();
+		let work = WorkBuilder::new()
+			.url(url.clone())
+			.method(Method::POST)
+			.body("hello")
+			.rate_limit(10_f64)
+			.build()
+			.unwrap();
+		assert_eq!(work.targets[0].method, Method::POST);
+		assert_eq!(&work.targets[0].body[..], b"hello");
+		assert_eq!(work.rate_limit, Some(10_f64));
+		assert_eq!(work.workers, 50);
+		assert_eq!(
+			WorkBuilder::new().build().err().unwrap().to_string(),
+			"no url to request"
+		);
+		assert!(WorkBuilder::new()
+			.url(url.clone())
+			.workers(0)
+			.build()
+			.is_err());
+		assert_eq!(
+			WorkBuilder::new()
+				.url(url.clone())
+				.total_requests(10)
+				.build()
+				.err()
+				.unwrap()
+				.to_string(),
+			"total_requests of 10 can't keep 50 workers busy, lower workers or raise total_requests"
+		);
+		assert!(WorkBuilder::new()
+			.url(url.clone())
+			.total_requests(10)
+			.duration(Duration::from_secs(1))
+			.build()
+			.is_ok());
+		assert_eq!(
+			WorkBuilder::new()
+				.url(url.clone())
+				.weights(vec![1, 2])
+				.build()
+				.err()
+				.unwrap()
+				.to_string(),
+			"2 weights for 1 urls"
+		);
+		assert_eq!(
+			WorkBuilder::new()
+				.url(url.clone())
+				.url(url.clone())
+				.weights(vec![0, 0])
+				.build()
+				.err()
+				.unwrap()
+				.to_string(),
+			"weights must be positive"
+		);
+		assert_eq!(
+			WorkBuilder::new()
+				.url(url.clone())
+				.rate_limit(0_f64)
+				.build()
+				.err()
+				.unwrap()
+				.to_string(),
+			"rate_limit must be a positive rate, not 0"
+		);
+		assert!(WorkBuilder::new()
+			.url(url.clone())
+			.global_rate_limit(f64::NAN)
+			.build()
+			.is_err());
+		assert!(WorkBuilder::new()
+			.url(url)
+			.rate_limit(-1_f64)
+			.build()
+			.is_err());
+	}
+}